@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE tokens
+	ADD COLUMN time	INT	UNSIGNED	NOT NULL	DEFAULT 0;
+
+UPDATE tokens SET time = UNIX_TIMESTAMP();
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- 0: 上下级关系, 1: 文中的链接
+ALTER TABLE post_edge
+	ADD COLUMN relation	TINYINT	UNSIGNED	NOT NULL	DEFAULT 0;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- 已有文章的 slug 由 `notes_lib::migrate` 生成
+ALTER TABLE posts
+	ADD COLUMN slug	VARCHAR(191)	UNIQUE;
+
+CREATE TABLE post_slug_redirects(
+	slug		VARCHAR(191)			NOT NULL,
+	post_id		INT		UNSIGNED	NOT NULL,
+	PRIMARY KEY (`slug`)
+);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE tags(
+	id		INT UNSIGNED	AUTO_INCREMENT,
+	name	VARCHAR(191)	NOT NULL	UNIQUE,
+	PRIMARY KEY (`id`)
+);
+
+CREATE TABLE post_tags(
+	post_id		INT	UNSIGNED	NOT NULL,
+	tag_id		INT	UNSIGNED	NOT NULL,
+	PRIMARY KEY (`post_id`, `tag_id`),
+	INDEX (`tag_id`)
+);
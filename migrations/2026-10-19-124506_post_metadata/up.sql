@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- 已有文章的字段由 `notes_lib::migrate` 解析
+CREATE TABLE post_metadata(
+	post_id		INT	UNSIGNED	NOT NULL,
+	name		VARCHAR(64)		NOT NULL,
+	value		VARCHAR(191)	NOT NULL,
+	PRIMARY KEY (`post_id`, `name`, `value`),
+	INDEX (`name`, `value`)
+);
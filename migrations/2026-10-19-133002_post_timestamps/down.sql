@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE histories DROP COLUMN user_id;
+ALTER TABLE posts
+	DROP COLUMN created_at,
+	DROP COLUMN updated_at,
+	DROP COLUMN created_by,
+	DROP COLUMN updated_by;
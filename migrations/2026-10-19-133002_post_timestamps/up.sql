@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE posts
+	ADD COLUMN created_at	INT	UNSIGNED	NOT NULL	DEFAULT 0,
+	ADD COLUMN updated_at	INT	UNSIGNED	NOT NULL	DEFAULT 0,
+	ADD COLUMN created_by	INT	UNSIGNED,
+	ADD COLUMN updated_by	INT	UNSIGNED;
+
+ALTER TABLE histories
+	ADD COLUMN user_id	INT	UNSIGNED;
+
+-- 已有文章的时间取自历史记录，作者无从得知
+UPDATE posts SET
+	created_at = COALESCE((SELECT MIN(time) FROM histories WHERE histories.post_id = posts.id), 0),
+	updated_at = COALESCE((SELECT MAX(time) FROM histories WHERE histories.post_id = posts.id), 0);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE posts
+	ADD COLUMN revision	INT	UNSIGNED	NOT NULL	DEFAULT 1;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE post_edge DROP COLUMN reattached_from;
+ALTER TABLE post_edge DROP COLUMN deleted_at;
+ALTER TABLE posts DROP COLUMN deleted_at;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE posts
+	ADD COLUMN deleted_at	INT	UNSIGNED,
+	ADD INDEX (`deleted_at`);
+
+ALTER TABLE post_edge
+	ADD COLUMN deleted_at	INT	UNSIGNED,
+	ADD COLUMN reattached_from	INT	UNSIGNED;
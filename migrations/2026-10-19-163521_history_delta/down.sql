@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+-- Run `notes history convert --full` first, otherwise delta histories lose their content
+ALTER TABLE histories
+	DROP INDEX `base_id`,
+	DROP COLUMN delta,
+	DROP COLUMN base_id;
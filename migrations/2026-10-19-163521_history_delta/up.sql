@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE histories
+	ADD COLUMN base_id	INT	UNSIGNED,
+	ADD COLUMN delta	MEDIUMBLOB,
+	ADD INDEX (`base_id`);
@@ -1,4 +1,5 @@
 //! 用户登陆的封装
+use crate::config::DEFAULT_TOKEN_LEN;
 use crate::token::Token;
 use crate::user::User;
 use crate::{DbConn, NoteError};
@@ -72,6 +73,10 @@ impl AuthUser {
     }
     /// 增加一个 Token
     pub fn add_token(&self, conn: &DbConn) -> Result<String, NoteError> {
+        self.add_token_with_len(conn, DEFAULT_TOKEN_LEN)
+    }
+    /// 增加一个长度为 `token_len` 的 Token
+    pub fn add_token_with_len(&self, conn: &DbConn, token_len: u32) -> Result<String, NoteError> {
         match self.level {
             AuthLevel::Password => (),
             _ => {
@@ -81,18 +86,17 @@ impl AuthUser {
             }
         };
 
-        let token = Token::new(self.id);
+        let token = Token::with_len(self.id, token_len);
         token.insert(conn, &*self)?;
         Ok(String::from(token.get_token()))
     }
-}
-
-/// 通过 Auth 枚举获得 AuthUser
-impl TryFrom<(Auth, &DbConn)> for AuthUser {
-    type Error = NoteError;
-    fn try_from(item: (Auth, &DbConn)) -> Result<AuthUser, Self::Error> {
-        let (auth, conn) = item;
 
+    /// 通过 Auth 枚举登陆，Token 超过 `token_lifetime` 秒视为无效（0 为永不过期）
+    pub fn authenticate(
+        auth: Auth,
+        conn: &DbConn,
+        token_lifetime: u64,
+    ) -> Result<AuthUser, NoteError> {
         match &auth {
             Auth::Password((user_name, user_password)) => {
                 let user = User::from_nickname(user_name, &conn).map_err(|err| {
@@ -107,7 +111,7 @@ impl TryFrom<(Auth, &DbConn)> for AuthUser {
                 }
             }
             Auth::Token((user_id, user_token)) => {
-                match Token::verify(user_id, user_token, &conn)? {
+                match Token::verify_within(user_id, user_token, token_lifetime, &conn)? {
                     false => Err(NoteError::AuthError("Wrong token".to_string())),
                     true => {
                         let user = User::from_user_id(*user_id, &conn).map_err(|err| {
@@ -125,6 +129,15 @@ impl TryFrom<(Auth, &DbConn)> for AuthUser {
     }
 }
 
+/// 通过 Auth 枚举获得 AuthUser
+impl TryFrom<(Auth, &DbConn)> for AuthUser {
+    type Error = NoteError;
+    fn try_from(item: (Auth, &DbConn)) -> Result<AuthUser, Self::Error> {
+        let (auth, conn) = item;
+        AuthUser::authenticate(auth, conn, 0)
+    }
+}
+
 /// 应当确保这个方法仅在确定验证过后调用
 impl From<(&User, AuthLevel)> for AuthUser {
     fn from(item: (&User, AuthLevel)) -> AuthUser {
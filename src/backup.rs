@@ -0,0 +1,614 @@
+//! 完整的数据备份与恢复
+//!
+//! 备份为 JSON Lines：第一行是文件头，记录备份格式与数据库的版本；之后每行一条记录；
+//! 最后一行记录各类记录的数量与之前所有记录行的 SHA-256，恢复时据此检查备份是否完整。
+//! 标签与元数据由文章内容生成，不写入备份，恢复后重新生成；
+//! 历史记录以完整内容写入备份，恢复后重新压缩为差量
+use crate::history::{History, HistoryStorage};
+use crate::raw::{RawEdge, RawHistory, RawPost, RawSlugRedirect, RawToken, RawUser};
+use crate::{DbConn, NoteError};
+
+use diesel::Connection;
+use sha2::{Digest, Sha256};
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, Write};
+
+/// 文件头中的格式名
+pub const FORMAT: &str = "notes-lib-backup";
+/// 当前的备份格式版本
+pub const FORMAT_VERSION: u32 = 1;
+/// 分批读取文章与历史记录时每批的条数
+const BATCH_SIZE: i64 = 500;
+
+/// 备份选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DumpOptions {
+    /// 是否包含密码哈希与 Token，不包含时恢复出的用户密码为空，无法登陆
+    pub credentials: bool,
+}
+
+impl Default for DumpOptions {
+    fn default() -> DumpOptions {
+        DumpOptions { credentials: true }
+    }
+}
+
+/// 备份的摘要，写在备份的最后一行
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupSummary {
+    /// 各类记录的数量
+    pub counts: BTreeMap<String, u64>,
+    /// 所有记录行的 SHA-256
+    pub checksum: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+    schema_version: String,
+    created_at: u32,
+    credentials: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum Record {
+    User(RawUser),
+    Token(RawToken),
+    Post(RawPost),
+    SlugRedirect(RawSlugRedirect),
+    Edge(RawEdge),
+    History(RawHistory),
+    End(BackupSummary),
+}
+
+impl Record {
+    fn kind(&self) -> &'static str {
+        match self {
+            Record::User(_) => "user",
+            Record::Token(_) => "token",
+            Record::Post(_) => "post",
+            Record::SlugRedirect(_) => "slug_redirect",
+            Record::Edge(_) => "edge",
+            Record::History(_) => "history",
+            Record::End(_) => "end",
+        }
+    }
+}
+
+/// 逐行写入记录，同时计算摘要
+struct BackupWriter<W: Write> {
+    writer: W,
+    hasher: Sha256,
+    counts: BTreeMap<String, u64>,
+}
+
+impl<W: Write> BackupWriter<W> {
+    fn write_line<T: serde::Serialize>(&mut self, value: &T) -> Result<String, NoteError> {
+        let mut line = serde_json::to_string(value).map_err(|err| {
+            NoteError::Validation(format!("Failed to serialize backup record: {}", err))
+        })?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(|err| NoteError::io(String::from("Failed to write backup"), err))?;
+        Ok(line)
+    }
+
+    fn write(&mut self, record: Record) -> Result<(), NoteError> {
+        let line = self.write_line(&record)?;
+        self.hasher.update(line.as_bytes());
+        *self.counts.entry(String::from(record.kind())).or_insert(0) += 1;
+        Ok(())
+    }
+}
+
+/// 将所有用户、文章、边与历史记录写入 `writer`
+///
+/// 在一个事务中读取，备份是数据库某一时刻的快照
+pub fn dump<W: Write>(
+    conn: &DbConn,
+    writer: W,
+    options: &DumpOptions,
+) -> Result<BackupSummary, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::{histories, post_edge, post_slug_redirects, posts, tokens, users};
+
+    conn.transaction::<_, NoteError, _>(|| {
+        let mut writer = BackupWriter {
+            writer,
+            hasher: Sha256::new(),
+            counts: BTreeMap::new(),
+        };
+        writer.write_line(&Header {
+            format: String::from(FORMAT),
+            version: FORMAT_VERSION,
+            schema_version: String::from(crate::migration::SCHEMA_VERSION),
+            created_at: chrono::Utc::now().timestamp() as u32,
+            credentials: options.credentials,
+        })?;
+
+        let user_list = users::table
+            .order(users::id.asc())
+            .load::<RawUser>(conn)
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to query users"), err))?;
+        for mut user in user_list {
+            if !options.credentials {
+                user.password = String::new();
+            }
+            writer.write(Record::User(user))?;
+        }
+        if options.credentials {
+            let token_list = tokens::table
+                .order(tokens::id.asc())
+                .load::<RawToken>(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(String::from("Failed to query tokens"), err)
+                })?;
+            for token in token_list {
+                writer.write(Record::Token(token))?;
+            }
+        }
+
+        let mut last_id = 0;
+        loop {
+            let post_list = posts::table
+                .filter(posts::id.gt(last_id))
+                .order(posts::id.asc())
+                .limit(BATCH_SIZE)
+                .load::<RawPost>(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(String::from("Failed to query posts"), err)
+                })?;
+            match post_list.last() {
+                Some(post) => last_id = post.id,
+                None => break,
+            }
+            for post in post_list {
+                writer.write(Record::Post(post))?;
+            }
+        }
+
+        let redirect_list = post_slug_redirects::table
+            .order(post_slug_redirects::slug.asc())
+            .load::<RawSlugRedirect>(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(String::from("Failed to query slug redirects"), err)
+            })?;
+        for redirect in redirect_list {
+            writer.write(Record::SlugRedirect(redirect))?;
+        }
+
+        let edge_list = post_edge::table
+            .order(post_edge::id.asc())
+            .load::<RawEdge>(conn)
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to query edges"), err))?;
+        for edge in edge_list {
+            writer.write(Record::Edge(edge))?;
+        }
+
+        let mut last_id = 0;
+        loop {
+            let history_list = histories::table
+                .filter(histories::id.gt(last_id))
+                .order(histories::id.asc())
+                .limit(BATCH_SIZE)
+                .load::<RawHistory>(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(String::from("Failed to query histories"), err)
+                })?;
+            match history_list.last() {
+                Some(history) => last_id = history.id,
+                None => break,
+            }
+            // 差量还原为完整内容，备份不依赖差量的格式
+            for history in History::from_raw(conn, history_list)? {
+                writer.write(Record::History(RawHistory {
+                    id: history.get_id(),
+                    post_id: history.get_post_id(),
+                    time: history.get_time(),
+                    markdown: Some(String::from(history.get_markdown())),
+                    user_id: history.get_user_id(),
+                    base_id: None,
+                    delta: None,
+                }))?;
+            }
+        }
+
+        let summary = BackupSummary {
+            counts: writer.counts.clone(),
+            checksum: format!("{:x}", writer.hasher.clone().finalize()),
+        };
+        writer.write_line(&Record::End(summary.clone()))?;
+        writer
+            .writer
+            .flush()
+            .map_err(|err| NoteError::io(String::from("Failed to write backup"), err))?;
+        Ok(summary)
+    })
+}
+
+/// 从 `reader` 恢复备份
+///
+/// 数据库需要已迁移到当前版本，并且除了迁移时插入的 Index 文章外没有其他数据。
+/// 恢复在一个事务中进行，备份不完整、校验失败或记录引用了不存在的文章或用户时不会写入任何数据
+pub fn restore<R: BufRead>(conn: &DbConn, reader: R) -> Result<BackupSummary, NoteError> {
+    crate::migration::check_schema_version(conn)?;
+
+    conn.transaction::<_, NoteError, _>(|| {
+        clear_seed(conn)?;
+
+        let mut lines = reader.lines().enumerate();
+        let header = match lines.next() {
+            Some((_, line)) => {
+                let line =
+                    line.map_err(|err| NoteError::io(String::from("Failed to read backup"), err))?;
+                serde_json::from_str::<Header>(&line).map_err(|err| {
+                    NoteError::Validation(format!("Invalid backup header: {}", err))
+                })?
+            }
+            None => return Err(NoteError::Validation(String::from("Backup is empty"))),
+        };
+        check_header(&header)?;
+
+        let mut restorer = Restorer {
+            conn,
+            hasher: Sha256::new(),
+            counts: BTreeMap::new(),
+            user_ids: HashSet::new(),
+            post_ids: HashSet::new(),
+        };
+        let mut summary = None;
+        for (index, line) in lines {
+            let line =
+                line.map_err(|err| NoteError::io(String::from("Failed to read backup"), err))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if summary.is_some() {
+                return Err(NoteError::Validation(format!(
+                    "Unexpected record after end of backup at line {}",
+                    index + 1
+                )));
+            }
+
+            let record = serde_json::from_str::<Record>(&line).map_err(|err| {
+                NoteError::Validation(format!(
+                    "Invalid backup record at line {}: {}",
+                    index + 1,
+                    err
+                ))
+            })?;
+            match record {
+                Record::End(end) => summary = Some(end),
+                record => {
+                    restorer.hasher.update(line.as_bytes());
+                    restorer.hasher.update(b"\n");
+                    restorer.insert(record).map_err(|err| match err {
+                        NoteError::Validation(message) => {
+                            NoteError::Validation(format!("{} at line {}", message, index + 1))
+                        }
+                        err => err,
+                    })?;
+                }
+            }
+        }
+
+        let summary = summary.ok_or_else(|| {
+            NoteError::Validation(String::from("Backup is truncated: missing end record"))
+        })?;
+        if summary.counts != restorer.counts {
+            return Err(NoteError::Validation(format!(
+                "Backup record counts do not match: expected {:?}, found {:?}",
+                summary.counts, restorer.counts
+            )));
+        }
+        let checksum = format!("{:x}", restorer.hasher.finalize());
+        if summary.checksum != checksum {
+            return Err(NoteError::Validation(format!(
+                "Backup checksum does not match: expected {}, found {}",
+                summary.checksum, checksum
+            )));
+        }
+
+        crate::slug::backfill(conn)?;
+        crate::tag::backfill(conn)?;
+        crate::front_matter::backfill(conn)?;
+        crate::history::convert(conn, HistoryStorage::Delta)?;
+        Ok(summary)
+    })
+}
+
+fn check_header(header: &Header) -> Result<(), NoteError> {
+    if header.format != FORMAT {
+        return Err(NoteError::Validation(format!(
+            "Not a notes backup: format is {}",
+            header.format
+        )));
+    }
+    if header.version != FORMAT_VERSION {
+        return Err(NoteError::Validation(format!(
+            "Unsupported backup version {}, expected {}",
+            header.version, FORMAT_VERSION
+        )));
+    }
+    if header.schema_version != crate::migration::SCHEMA_VERSION {
+        return Err(NoteError::Validation(format!(
+            "Backup was made with schema {}, but the database is at {}",
+            header.schema_version,
+            crate::migration::SCHEMA_VERSION
+        )));
+    }
+    Ok(())
+}
+
+/// 检查数据库是否为空，并删除迁移时插入的 Index 文章
+fn clear_seed(conn: &DbConn) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::{
+        histories, post_edge, post_metadata, post_slug_redirects, post_tags, posts, tags, tokens,
+        users,
+    };
+
+    let count_error =
+        |err| NoteError::from_diesel(String::from("Failed to check database is empty"), err);
+    let others = users::table
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(count_error)?
+        + tokens::table
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(count_error)?
+        + post_edge::table
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(count_error)?
+        + histories::table
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(count_error)?
+        + posts::table
+            .filter(posts::id.ne(crate::migration::INDEX_POST_ID))
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(count_error)?;
+    if others > 0 {
+        return Err(NoteError::conflict(String::from(
+            "Database is not empty, restore requires a freshly migrated database",
+        )));
+    }
+
+    let delete_error =
+        |err| NoteError::from_diesel(String::from("Failed to delete index post"), err);
+    diesel::delete(post_metadata::table)
+        .execute(conn)
+        .map_err(delete_error)?;
+    diesel::delete(post_tags::table)
+        .execute(conn)
+        .map_err(delete_error)?;
+    diesel::delete(tags::table)
+        .execute(conn)
+        .map_err(delete_error)?;
+    diesel::delete(post_slug_redirects::table)
+        .execute(conn)
+        .map_err(delete_error)?;
+    diesel::delete(posts::table)
+        .execute(conn)
+        .map_err(delete_error)?;
+    Ok(())
+}
+
+/// 逐条插入记录，检查引用并计算摘要
+struct Restorer<'a> {
+    conn: &'a DbConn,
+    hasher: Sha256,
+    counts: BTreeMap<String, u64>,
+    user_ids: HashSet<u32>,
+    post_ids: HashSet<u32>,
+}
+
+impl Restorer<'_> {
+    fn check_user(&self, user_id: Option<u32>) -> Result<(), NoteError> {
+        match user_id {
+            Some(user_id) if !self.user_ids.contains(&user_id) => Err(NoteError::Validation(
+                format!("Record references unknown user {}", user_id),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_post(&self, post_id: u32) -> Result<(), NoteError> {
+        match self.post_ids.contains(&post_id) {
+            true => Ok(()),
+            false => Err(NoteError::Validation(format!(
+                "Record references unknown post {}",
+                post_id
+            ))),
+        }
+    }
+
+    fn insert(&mut self, record: Record) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::{histories, post_edge, post_slug_redirects, posts, tokens, users};
+
+        let conn = self.conn;
+        let kind = record.kind();
+        let insert_error = |err| NoteError::from_diesel(format!("Failed to restore {}", kind), err);
+        match record {
+            Record::User(user) => {
+                self.user_ids.insert(user.id);
+                diesel::insert_into(users::table)
+                    .values(user)
+                    .execute(conn)
+                    .map_err(insert_error)?;
+            }
+            Record::Token(token) => {
+                self.check_user(Some(token.user_id))?;
+                diesel::insert_into(tokens::table)
+                    .values(token)
+                    .execute(conn)
+                    .map_err(insert_error)?;
+            }
+            Record::Post(post) => {
+                self.check_user(post.created_by)?;
+                self.check_user(post.updated_by)?;
+                self.post_ids.insert(post.id);
+                diesel::insert_into(posts::table)
+                    .values(post)
+                    .execute(conn)
+                    .map_err(insert_error)?;
+            }
+            Record::SlugRedirect(redirect) => {
+                self.check_post(redirect.post_id)?;
+                diesel::insert_into(post_slug_redirects::table)
+                    .values(redirect)
+                    .execute(conn)
+                    .map_err(insert_error)?;
+            }
+            Record::Edge(edge) => {
+                self.check_post(edge.from_post)?;
+                self.check_post(edge.to_post)?;
+                diesel::insert_into(post_edge::table)
+                    .values(edge)
+                    .execute(conn)
+                    .map_err(insert_error)?;
+            }
+            Record::History(history) => {
+                self.check_post(history.post_id)?;
+                self.check_user(history.user_id)?;
+                if history.base_id.is_some() || history.delta.is_some() {
+                    return Err(NoteError::Validation(format!(
+                        "History {} is stored as a delta, backups must contain full histories",
+                        history.id
+                    )));
+                }
+                diesel::insert_into(histories::table)
+                    .values(history)
+                    .execute(conn)
+                    .map_err(insert_error)?;
+            }
+            Record::End(_) => unreachable!(),
+        }
+
+        *self.counts.entry(String::from(kind)).or_insert(0) += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_header, dump, restore, DumpOptions, Header, Record, FORMAT, FORMAT_VERSION};
+    use crate::auth::{AuthInsert, AuthUpdate};
+    use crate::post::Post;
+    use crate::raw::RawSlugRedirect;
+    use crate::test_db;
+    use crate::DbConn;
+
+    /// 删除所有数据，包括迁移时插入的 Index 文章
+    fn clear(conn: &DbConn) {
+        use crate::diesel::*;
+        use crate::schema::{
+            histories, post_edge, post_metadata, post_slug_redirects, post_tags, posts, tags,
+            tokens, users,
+        };
+
+        diesel::delete(post_metadata::table).execute(conn).unwrap();
+        diesel::delete(post_tags::table).execute(conn).unwrap();
+        diesel::delete(tags::table).execute(conn).unwrap();
+        diesel::delete(post_slug_redirects::table)
+            .execute(conn)
+            .unwrap();
+        diesel::delete(post_edge::table).execute(conn).unwrap();
+        diesel::delete(histories::table).execute(conn).unwrap();
+        diesel::delete(posts::table).execute(conn).unwrap();
+        diesel::delete(tokens::table).execute(conn).unwrap();
+        diesel::delete(users::table).execute(conn).unwrap();
+    }
+
+    #[test]
+    fn records_are_tagged_with_type() {
+        let record = Record::SlugRedirect(RawSlugRedirect {
+            slug: String::from("old"),
+            post_id: 2,
+        });
+        let line = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            line,
+            r#"{"type":"slug_redirect","data":{"slug":"old","post_id":2}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Record>(&line).unwrap().kind(),
+            "slug_redirect"
+        );
+    }
+
+    #[test]
+    fn header_versions_are_checked() {
+        let mut header = Header {
+            format: String::from(FORMAT),
+            version: FORMAT_VERSION,
+            schema_version: String::from(crate::migration::SCHEMA_VERSION),
+            created_at: 0,
+            credentials: false,
+        };
+        assert!(check_header(&header).is_ok());
+        header.version += 1;
+        assert!(check_header(&header).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a MySQL database in NOTES_TEST_DATABASE_URL"]
+    fn dump_then_restore_round_trips() {
+        let conn = test_db::connect();
+        let user = test_db::admin(&conn);
+
+        let post_id = Post::new(None, String::from("Rust"), Some(String::from("#rust")))
+            .insert(&conn, &user)
+            .unwrap();
+        let mut post = Post::new(
+            Some(post_id),
+            String::from("Rust notes"),
+            Some(String::from("---\naliases: [rs]\n---\n#rust #notes")),
+        );
+        post.set_revision(Post::from_id(&conn, post_id).unwrap().get_revision());
+        post.update(&conn, &user).unwrap();
+        let trashed = Post::new(None, String::from("Draft"), Some(String::from("draft")))
+            .insert_under(&conn, &user, post_id)
+            .unwrap();
+        Post::from_id(&conn, trashed)
+            .unwrap()
+            .trash(&conn, &user, crate::migration::INDEX_POST_ID)
+            .unwrap();
+
+        let mut backup = vec![];
+        let summary = dump(&conn, &mut backup, &DumpOptions::default()).unwrap();
+        assert_eq!(summary.counts.get("history"), Some(&3));
+
+        // 恢复需要空的数据库
+        assert!(restore(&conn, &backup[..]).is_err());
+        clear(&conn);
+        assert_eq!(restore(&conn, &backup[..]).unwrap(), summary);
+
+        let post = Post::from_id(&conn, post_id).unwrap();
+        assert_eq!(post.get_title(), "Rust notes");
+        let mut tags = post
+            .tags(&conn)
+            .unwrap()
+            .iter()
+            .map(|tag| String::from(tag.get_name()))
+            .collect::<Vec<String>>();
+        tags.sort();
+        assert_eq!(tags, vec!["notes", "rust"]);
+        assert_eq!(Post::from_alias(&conn, "rs").unwrap().get_id(), post_id);
+        assert!(Post::from_trash(&conn, trashed).is_ok());
+
+        let mut again = vec![];
+        assert_eq!(
+            dump(&conn, &mut again, &DumpOptions::default()).unwrap(),
+            summary
+        );
+    }
+}
@@ -0,0 +1,488 @@
+//! notes-lib 的 REST HTTP 服务
+//!
+//! 用法：`notes-server [config.toml]`，监听地址由环境变量 `NOTES_LISTEN` 指定
+//!
+//! 除 `POST /users` 与 `POST /tokens` 外，所有请求都需要带上
+//! `Authorization: Bearer <user_id>:<token>`
+#[macro_use]
+extern crate serde_derive;
+
+use notes_lib::auth::{Auth, AuthDelete, AuthInsert, AuthUpdate, AuthUser};
+use notes_lib::edge::Edge;
+use notes_lib::front_matter::INDEXED_FIELDS;
+use notes_lib::graph::{Graph, GraphOptions};
+use notes_lib::history::{History, RecentFilter};
+use notes_lib::post::{Post, PostFilter};
+use notes_lib::tag::Tag;
+use notes_lib::user::User;
+use notes_lib::{NoteError, Notes, NotesConfig};
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use std::path::Path;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8000";
+
+type Reply = Result<(u16, Value), NoteError>;
+
+#[derive(Deserialize)]
+struct PasswordBody {
+    nickname: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct UserBody {
+    nickname: String,
+    password: String,
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct UpdateUserBody {
+    current_password: String,
+    nickname: String,
+    password: String,
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct PostBody {
+    title: String,
+    markdown: Option<String>,
+    /// 仅创建时使用，默认为配置中的根文章
+    parent: Option<u32>,
+    /// 修改时必须给出，也可以使用 `If-Match` 头给出 ETag
+    revision: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct MergeBody {
+    /// 共同祖先的历史记录 id
+    base_history: u32,
+    ours: String,
+    /// 默认为文章的当前内容
+    theirs: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EdgeBody {
+    from_post: u32,
+    to_post: u32,
+}
+
+#[derive(Deserialize)]
+struct TagBody {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct MergeTagBody {
+    into: u32,
+}
+
+fn main() {
+    let config_path = std::env::args().nth(1);
+    let config = NotesConfig::load(config_path.as_ref().map(Path::new))
+        .unwrap_or_else(|err| exit_with(&err));
+    let notes = Notes::connect(config).unwrap_or_else(|err| exit_with(&err));
+
+    let listen = std::env::var("NOTES_LISTEN").unwrap_or_else(|_| String::from(DEFAULT_LISTEN));
+    let server = Server::http(&listen).unwrap_or_else(|err| {
+        eprintln!("Failed to listen on {}: {}", listen, err);
+        std::process::exit(1);
+    });
+    println!("Listening on {}", listen);
+
+    for mut request in server.incoming_requests() {
+        let (status, body) = match handle(&notes, &mut request) {
+            Ok(reply) => reply,
+            Err(err) => (err.http_status(), error_body(&err)),
+        };
+
+        let body = match body {
+            Value::Null => String::new(),
+            body => body.to_string(),
+        };
+        let response = Response::from_string(body)
+            .with_status_code(status)
+            .with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+            );
+        if let Err(err) = request.respond(response) {
+            eprintln!("Failed to respond: {}", err);
+        }
+    }
+}
+
+fn exit_with(err: &NoteError) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(1);
+}
+
+fn error_body(err: &NoteError) -> Value {
+    json!({
+        "code": err.code(),
+        "message": err.to_string(),
+        "error": err,
+    })
+}
+
+fn handle(notes: &Notes, request: &mut Request) -> Reply {
+    let method = request.method().clone();
+    let url = String::from(request.url());
+    let path = url.split('?').next().unwrap_or("");
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<&str>>();
+
+    match (&method, segments.as_slice()) {
+        (Method::Post, ["tokens"]) => create_token(notes, request),
+        (Method::Post, ["users"]) => create_user(notes, request),
+        _ => {
+            let user = authorize(notes, request)?;
+            let conn = notes.conn();
+            match (&method, segments.as_slice()) {
+                (Method::Get, ["users", "me"]) => Ok((200, user_json(&user))),
+                (Method::Put, ["users", "me"]) => update_user(notes, request, &user),
+
+                (Method::Get, ["posts"]) => {
+                    Ok((200, json!(Post::list(conn, &post_filter(&url)?)?)))
+                }
+                (Method::Post, ["posts"]) => create_post(notes, request, &user),
+                (Method::Get, ["posts", id]) => {
+                    Ok((200, json!(Post::from_id(conn, parse_id(id)?)?)))
+                }
+                (Method::Get, ["slugs", post_slug]) => {
+                    let post_slug = notes_lib::slug::decode(post_slug);
+                    Ok((200, json!(Post::from_slug(conn, &post_slug)?)))
+                }
+                (Method::Put, ["posts", id]) => update_post(notes, request, &user, parse_id(id)?),
+                (Method::Delete, ["posts", id]) => {
+                    notes.delete_post(&Post::from_id(conn, parse_id(id)?)?, &user)?;
+                    Ok((204, Value::Null))
+                }
+                (Method::Get, ["posts", id, "children"]) => {
+                    Ok((200, json!(Edge::get_to_list(conn, parse_id(id)?)?)))
+                }
+                (Method::Get, ["posts", id, "parents"]) => {
+                    Ok((200, json!(Edge::get_from_list(conn, parse_id(id)?)?)))
+                }
+                (Method::Get, ["posts", id, "links"]) => {
+                    let post = Post::from_id(conn, parse_id(id)?)?;
+                    Ok((200, json!(post.resolve_links(conn)?)))
+                }
+                (Method::Get, ["posts", id, "backlinks"]) => {
+                    let post = Post::from_id(conn, parse_id(id)?)?;
+                    Ok((200, json!(post.backlinks(conn)?)))
+                }
+                (Method::Post, ["posts", id, "merge"]) => {
+                    let body = read_json::<MergeBody>(request)?;
+                    let post = Post::from_id(conn, parse_id(id)?)?;
+                    let theirs = body
+                        .theirs
+                        .unwrap_or_else(|| String::from(post.get_markdown()));
+                    let result = post.merge(conn, body.base_history, &theirs, &body.ours)?;
+                    Ok((
+                        200,
+                        json!({
+                            "revision": post.get_revision(),
+                            "clean": result.is_clean(),
+                            "markdown": result.with_markers(),
+                            "chunks": result.chunks,
+                        }),
+                    ))
+                }
+                (Method::Get, ["posts", id, "metadata"]) => {
+                    let post = Post::from_id(conn, parse_id(id)?)?;
+                    Ok((200, json!(post.metadata()?)))
+                }
+                (Method::Get, ["posts", id, "tags"]) => {
+                    let post = Post::from_id(conn, parse_id(id)?)?;
+                    Ok((200, json!(post.tags(conn)?)))
+                }
+                (Method::Get, ["posts", id, "blame"]) => {
+                    let post = Post::from_id(conn, parse_id(id)?)?;
+                    Ok((200, json!(post.blame(conn)?)))
+                }
+                (Method::Get, ["posts", id, "history"]) => {
+                    Ok((200, json!(History::get_history(parse_id(id)?, conn)?)))
+                }
+
+                (Method::Get, ["trash"]) => Ok((200, json!(Post::list_trash(conn)?))),
+                (Method::Post, ["trash", "purge"]) => {
+                    Ok((200, json!({ "purged": notes.purge_trash(&user)? })))
+                }
+                (Method::Post, ["trash", id, "restore"]) => {
+                    let post = Post::from_trash(conn, parse_id(id)?)?;
+                    post.restore(conn, &user)?;
+                    Ok((200, json!(Post::from_id(conn, post.get_id())?)))
+                }
+                (Method::Delete, ["trash", id]) => {
+                    Post::from_trash(conn, parse_id(id)?)?.purge(conn, &user)?;
+                    Ok((204, Value::Null))
+                }
+
+                (Method::Get, ["histories"]) => {
+                    let (since, limit, filter) = recent_filter(&url)?;
+                    Ok((200, json!(History::recent(conn, since, limit, &filter)?)))
+                }
+                (Method::Post, ["histories", "prune"]) => {
+                    let dry_run = query_flag(&url, "dry_run");
+                    Ok((200, json!(notes.prune_histories(&user, dry_run)?)))
+                }
+                (Method::Get, ["histories", id]) => {
+                    Ok((200, json!(History::from_id(conn, parse_id(id)?)?)))
+                }
+
+                (Method::Get, ["tags"]) => Ok((200, json!(Tag::list(conn)?))),
+                (Method::Get, ["graph"]) => {
+                    Ok((200, Graph::load(conn, &graph_options(&url)?)?.to_json()))
+                }
+                (Method::Put, ["tags", id]) => {
+                    let body = read_json::<TagBody>(request)?;
+                    let tag = Tag::from_id(conn, parse_id(id)?)?;
+                    tag.rename(conn, &user, &body.name)?;
+                    Ok((200, json!(Tag::from_id(conn, tag.get_id())?)))
+                }
+                (Method::Post, ["tags", id, "merge"]) => {
+                    let body = read_json::<MergeTagBody>(request)?;
+                    let into = Tag::from_id(conn, body.into)?;
+                    Tag::from_id(conn, parse_id(id)?)?.merge(conn, &user, &into)?;
+                    Ok((200, json!(into)))
+                }
+
+                (Method::Post, ["edges"]) => {
+                    let body = read_json::<EdgeBody>(request)?;
+                    let id = Edge::new(body.from_post, body.to_post).insert(conn, &user)?;
+                    Ok((201, json!({ "id": id })))
+                }
+                (Method::Get, ["edges", id]) => {
+                    Ok((200, json!(Edge::from_id(conn, parse_id(id)?)?)))
+                }
+                (Method::Delete, ["edges", id]) => {
+                    Edge::from_id(conn, parse_id(id)?)?.delete(conn, &user)?;
+                    Ok((204, Value::Null))
+                }
+
+                _ => Err(NoteError::not_found("route", path)),
+            }
+        }
+    }
+}
+
+/// 从 `Authorization: Bearer <user_id>:<token>` 登陆
+fn authorize(notes: &Notes, request: &Request) -> Result<AuthUser, NoteError> {
+    let header = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .ok_or_else(|| NoteError::AuthError(String::from("Missing Authorization header")))?;
+
+    let credential = header
+        .value
+        .as_str()
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| NoteError::AuthError(String::from("Only Bearer auth is supported")))?;
+    let mut parts = credential.trim().splitn(2, ':');
+    let user_id = parts
+        .next()
+        .and_then(|user_id| user_id.parse::<u32>().ok())
+        .ok_or_else(|| NoteError::AuthError(String::from("Malformed bearer token")))?;
+    let token = parts
+        .next()
+        .ok_or_else(|| NoteError::AuthError(String::from("Malformed bearer token")))?;
+
+    notes.login(Auth::Token((user_id, String::from(token))))
+}
+
+fn create_token(notes: &Notes, request: &mut Request) -> Reply {
+    let body = read_json::<PasswordBody>(request)?;
+    let user = notes.login(Auth::Password((body.nickname, body.password)))?;
+    let token = notes.add_token(&user)?;
+    Ok((201, json!({ "user_id": user.get_id(), "token": token })))
+}
+
+fn create_user(notes: &Notes, request: &mut Request) -> Reply {
+    let body = read_json::<UserBody>(request)?;
+    let mut user = User::new(None, body.nickname, body.password, body.email);
+    let id = notes.register(&mut user)?;
+    Ok((201, json!({ "id": id })))
+}
+
+fn update_user(notes: &Notes, request: &mut Request, user: &AuthUser) -> Reply {
+    let body = read_json::<UpdateUserBody>(request)?;
+    // 修改资料需要密码登陆
+    let auth = notes.login(Auth::Password((
+        String::from(user.get_nickname()),
+        body.current_password,
+    )))?;
+    let profile = User::new(
+        Some(auth.get_id()),
+        body.nickname,
+        body.password,
+        body.email,
+    );
+    notes.update_user(&profile, &auth)?;
+    Ok((204, Value::Null))
+}
+
+fn create_post(notes: &Notes, request: &mut Request, user: &AuthUser) -> Reply {
+    let body = read_json::<PostBody>(request)?;
+    let post = Post::new(None, body.title, body.markdown);
+    let id = match body.parent {
+        Some(parent) => post.insert_under(notes.conn(), user, parent)?,
+        None => notes.insert_post(&post, user)?,
+    };
+    let links = Post::from_id(notes.conn(), id)?.resolve_links(notes.conn())?;
+    Ok((
+        201,
+        json!({ "id": id, "unresolved_links": links.unresolved }),
+    ))
+}
+
+fn update_post(notes: &Notes, request: &mut Request, user: &AuthUser, id: u32) -> Reply {
+    let body = read_json::<PostBody>(request)?;
+    Post::from_id(notes.conn(), id)?;
+    let revision = match body.revision.or(if_match(request, id)?) {
+        Some(revision) => revision,
+        None => {
+            return Err(NoteError::Validation(String::from(
+                "Revision is required, use the revision field or If-Match",
+            )))
+        }
+    };
+    let mut post = Post::new(Some(id), body.title, body.markdown);
+    post.set_revision(revision);
+    post.update(notes.conn(), user)?;
+    let links = post.resolve_links(notes.conn())?;
+    Ok((
+        200,
+        json!({
+            "post": Post::from_id(notes.conn(), id)?,
+            "unresolved_links": links.unresolved,
+        }),
+    ))
+}
+
+/// 从 `If-Match: "<id>-<revision>"` 中读取版本号
+fn if_match(request: &Request, id: u32) -> Result<Option<u32>, NoteError> {
+    let header = match request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("If-Match"))
+    {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    let etag = header.value.as_str().trim().trim_start_matches("W/");
+    etag.trim_matches('"')
+        .strip_prefix(&format!("{}-", id))
+        .and_then(|revision| revision.parse::<u32>().ok())
+        .map(Some)
+        .ok_or_else(|| NoteError::Validation(format!("Invalid If-Match: {}", etag)))
+}
+
+fn user_json(user: &AuthUser) -> Value {
+    json!({
+        "id": user.get_id(),
+        "nickname": user.get_nickname(),
+        "email": user.get_email(),
+        "admin": user.is_admin(),
+    })
+}
+
+/// 从 `?tag=a&tag=b&not_tag=c&status=draft&offset=0&limit=20` 中读取文章列表的筛选条件
+///
+/// `front_matter::INDEXED_FIELDS` 中的字段名用于按元数据筛选
+fn post_filter(url: &str) -> Result<PostFilter, NoteError> {
+    let mut filter = PostFilter::default();
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = notes_lib::slug::decode(&parts.next().unwrap_or("").replace('+', " "));
+        match key {
+            "tag" => filter.include_tags.push(value),
+            "not_tag" => filter.exclude_tags.push(value),
+            "offset" => filter.offset = parse_number(&value)?,
+            "limit" => filter.limit = Some(parse_number(&value)?),
+            field if INDEXED_FIELDS.contains(&field) => {
+                filter.metadata.push((String::from(field), value))
+            }
+            _ => (),
+        }
+    }
+    Ok(filter)
+}
+
+/// 从 `?root=1&depth=2&links&titles&tags` 中读取关系图的导出选项
+fn graph_options(url: &str) -> Result<GraphOptions, NoteError> {
+    let mut options = GraphOptions::default();
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, "true"));
+        let enabled = value != "false" && value != "0";
+        match key {
+            "root" => options.root_id = Some(parse_id(value)?),
+            "depth" => options.max_depth = Some(parse_number(value)? as u32),
+            "links" => options.links = enabled,
+            "titles" => options.titles = enabled,
+            "tags" => options.tags = enabled,
+            _ => (),
+        }
+    }
+    Ok(options)
+}
+
+/// 从 `?since=&limit=&user=&root=&tag=` 中读取最近修改的筛选条件
+fn recent_filter(url: &str) -> Result<(u32, i64, RecentFilter), NoteError> {
+    let mut since = 0;
+    let mut limit = 50;
+    let mut filter = RecentFilter::default();
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match key {
+            "since" => since = parse_number(value)? as u32,
+            "limit" => limit = parse_number(value)?,
+            "user" => filter.user_id = Some(parse_id(value)?),
+            "root" => filter.root_id = Some(parse_id(value)?),
+            "tag" => {
+                filter.tag = Some(notes_lib::slug::decode(&value.replace('+', " ")));
+            }
+            _ => (),
+        }
+    }
+    Ok((since, limit, filter))
+}
+
+/// 查询参数中的开关，`?name`、`?name=true` 与 `?name=1` 为真
+fn query_flag(url: &str, name: &str) -> bool {
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    query.split('&').any(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, "true"));
+        key == name && value != "false" && value != "0"
+    })
+}
+
+fn parse_number(number: &str) -> Result<i64, NoteError> {
+    number
+        .parse::<u32>()
+        .map(i64::from)
+        .map_err(|_| NoteError::Validation(format!("Invalid number: {}", number)))
+}
+
+fn parse_id(id: &str) -> Result<u32, NoteError> {
+    id.parse::<u32>()
+        .map_err(|_| NoteError::Validation(format!("Invalid id: {}", id)))
+}
+
+fn read_json<T: DeserializeOwned>(request: &mut Request) -> Result<T, NoteError> {
+    serde_json::from_reader(request.as_reader())
+        .map_err(|err| NoteError::Validation(format!("Invalid request body: {}", err)))
+}
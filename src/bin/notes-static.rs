@@ -0,0 +1,56 @@
+//! 将文章生成为静态网站
+//!
+//! 用法：`notes-static [--config <config.toml>] [--root <id>] [--title <title>] [--no-history] <out>`
+use notes_lib::site::SiteOptions;
+use notes_lib::{NoteError, Notes, NotesConfig};
+
+use structopt::StructOpt;
+
+use std::path::PathBuf;
+
+#[derive(StructOpt)]
+#[structopt(name = "notes-static", about = "Render notes to a static HTML site")]
+struct Opt {
+    /// 配置文件
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// 发布的根文章，默认为配置中的根文章
+    #[structopt(long)]
+    root: Option<u32>,
+    /// 网站标题
+    #[structopt(long, default_value = "Notes")]
+    title: String,
+    /// 不生成历史记录页面
+    #[structopt(long)]
+    no_history: bool,
+    /// 输出目录
+    #[structopt(parse(from_os_str))]
+    out: PathBuf,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if let Err(err) = run(opt) {
+        eprintln!("error[{}]: {}", err.code(), err);
+        std::process::exit(1);
+    }
+}
+
+fn run(opt: Opt) -> Result<(), NoteError> {
+    let config = NotesConfig::load(opt.config.as_deref())?;
+    let options = SiteOptions {
+        root_id: opt.root.unwrap_or(config.root_post_id),
+        title: opt.title,
+        history: !opt.no_history,
+    };
+    let notes = Notes::connect(config)?;
+
+    let report = notes_lib::site::build(notes.conn(), &opt.out, &options)?;
+    println!(
+        "Generated {} pages ({} history pages) in {}",
+        report.pages,
+        report.history_pages,
+        opt.out.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,783 @@
+//! notes-lib 的命令行客户端
+//!
+//! 需要登陆的命令通过 `--token <user_id>:<token>`（或环境变量 `NOTES_TOKEN`）使用 Token 登陆，
+//! 或通过 `--user <nickname>`（或环境变量 `NOTES_USER`）使用密码登陆，
+//! 密码从环境变量 `NOTES_PASSWORD` 读取，未设置时从标准输入读取
+use notes_lib::auth::{Auth, AuthDelete, AuthUpdate, AuthUser};
+use notes_lib::backup::DumpOptions;
+use notes_lib::edge::Edge;
+use notes_lib::feed::{FeedFormat, FeedOptions};
+use notes_lib::graph::{Graph, GraphFormat, GraphOptions};
+use notes_lib::history::{History, HistoryStorage, RecentFilter};
+use notes_lib::import::ImportOptions;
+use notes_lib::link::WikiLink;
+use notes_lib::post::{Post, PostFilter};
+use notes_lib::tag::Tag;
+use notes_lib::user::User;
+use notes_lib::{NoteError, Notes, NotesConfig};
+
+use structopt::StructOpt;
+
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(StructOpt)]
+#[structopt(name = "notes", about = "Manage notes from the command line")]
+struct Opt {
+    /// 配置文件
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// 使用 Token 登陆，格式为 `<user_id>:<token>`
+    #[structopt(long, env = "NOTES_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// 使用密码登陆的用户名
+    #[structopt(long, env = "NOTES_USER")]
+    user: Option<String>,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// 执行数据库迁移
+    Migrate,
+    /// 管理文章
+    Post(PostCommand),
+    /// 连接两篇文章
+    Link { from: u32, to: u32 },
+    /// 断开两篇文章的连接
+    Unlink { from: u32, to: u32 },
+    /// 查看历史记录
+    History(HistoryCommand),
+    /// 管理标签
+    Tag(TagCommand),
+    /// 管理回收站
+    Trash(TrashCommand),
+    /// 将所有文章导出为 Markdown 目录
+    Export {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+    },
+    /// 将文章之间的关系导出为 dot、graphml 或 json，写到标准输出
+    Graph {
+        #[structopt(long, default_value = "dot")]
+        format: GraphFormat,
+        /// 只导出从该文章出发可以到达的文章
+        #[structopt(long)]
+        root: Option<u32>,
+        /// 与根文章的最大距离
+        #[structopt(long)]
+        depth: Option<u32>,
+        /// 包含文中的链接
+        #[structopt(long)]
+        links: bool,
+        /// 带上标题
+        #[structopt(long)]
+        titles: bool,
+        /// 带上标签
+        #[structopt(long)]
+        tags: bool,
+    },
+    /// 生成最近修改的 Atom 或 RSS 订阅，写到标准输出
+    Feed {
+        #[structopt(long, default_value = "atom")]
+        format: FeedFormat,
+        #[structopt(long, default_value = "Recent changes")]
+        title: String,
+        /// 订阅自身的地址
+        #[structopt(long)]
+        url: String,
+        /// 文章地址的模板，可以使用 `{id}` 与 `{slug}`
+        #[structopt(long, default_value = "{slug}.html")]
+        post_url: String,
+        /// 只包含从该文章出发可以到达的文章
+        #[structopt(long)]
+        root: Option<u32>,
+        /// 只包含带有该标签的文章
+        #[structopt(long)]
+        tag: Option<String>,
+        #[structopt(long, default_value = "50")]
+        limit: i64,
+    },
+    /// 备份与恢复所有数据
+    Backup(BackupCommand),
+    /// 从 Markdown 目录（例如 Obsidian 仓库）导入文章
+    Import {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+        /// 上级文章，默认为根文章
+        #[structopt(long)]
+        parent: Option<u32>,
+        /// 附件复制到的目录
+        #[structopt(long, parse(from_os_str))]
+        attachments: Option<PathBuf>,
+        /// 附件目录对外的地址前缀
+        #[structopt(long, default_value = "/attachments")]
+        attachments_url: String,
+        /// 从 git 记录导入历史记录
+        #[structopt(long)]
+        git_history: bool,
+    },
+    /// 管理用户
+    User(UserCommand),
+    /// 管理 Token
+    Token(TokenCommand),
+}
+
+#[derive(StructOpt)]
+enum PostCommand {
+    /// 新建文章，未指定 `--file` 时打开 `$EDITOR`
+    New {
+        #[structopt(long)]
+        title: String,
+        /// 上级文章，默认为配置中的根文章
+        #[structopt(long)]
+        parent: Option<u32>,
+        #[structopt(long, parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// 编辑文章，未指定 `--file` 时打开 `$EDITOR`
+    Edit {
+        id: u32,
+        #[structopt(long)]
+        title: Option<String>,
+        #[structopt(long, parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// 显示文章，可以使用 id 或 slug
+    Show { post: String },
+    /// 删除文章
+    Delete { id: u32 },
+    /// 显示文章的元数据
+    Meta { id: u32 },
+    /// 逐行显示最后修改它的历史记录与作者
+    Blame { id: u32 },
+    /// 列出文章
+    List {
+        /// 需要带有的标签，可以重复
+        #[structopt(long)]
+        tag: Vec<String>,
+        /// 不能带有的标签，可以重复
+        #[structopt(long)]
+        not_tag: Vec<String>,
+        /// 需要满足的元数据，格式为 `<字段>=<值>`，如 `status=draft`，可以重复
+        #[structopt(long)]
+        meta: Vec<String>,
+        #[structopt(long, default_value = "0")]
+        offset: i64,
+        #[structopt(long)]
+        limit: Option<i64>,
+    },
+}
+
+#[derive(StructOpt)]
+enum BackupCommand {
+    /// 导出备份，未指定文件时写到标准输出
+    Dump {
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+        /// 不包含密码哈希与 Token
+        #[structopt(long)]
+        no_credentials: bool,
+    },
+    /// 恢复备份到刚迁移过的空数据库
+    Restore {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+enum TrashCommand {
+    /// 列出回收站中的文章
+    List,
+    /// 恢复文章
+    Restore { id: u32 },
+    /// 彻底删除文章，未指定 id 时删除超过保留天数的文章，需要管理员权限
+    Purge { id: Option<u32> },
+}
+
+#[derive(StructOpt)]
+enum TagCommand {
+    /// 列出所有标签
+    List,
+    /// 重命名标签，并改写带有该标签的文章
+    Rename { name: String, new_name: String },
+    /// 将标签合并到另一个标签，并改写带有该标签的文章
+    Merge { name: String, into: String },
+}
+
+#[derive(StructOpt)]
+enum HistoryCommand {
+    /// 列出文章的历史记录
+    List { post_id: u32 },
+    /// 显示某条历史记录
+    Show { id: u32 },
+    /// 列出所有文章最近的修改
+    Recent {
+        /// 只列出最近若干天内的修改
+        #[structopt(long)]
+        days: Option<u32>,
+        /// 只列出该用户的修改
+        #[structopt(long)]
+        user: Option<u32>,
+        /// 只包含从该文章出发可以到达的文章
+        #[structopt(long)]
+        root: Option<u32>,
+        /// 只包含带有该标签的文章
+        #[structopt(long)]
+        tag: Option<String>,
+        #[structopt(long, default_value = "50")]
+        limit: i64,
+    },
+    /// 按配置的保留策略精简历史记录，需要管理员权限
+    Prune {
+        /// 只列出将被删除的历史记录
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// 将已有的历史记录压缩为快照与差量，需要管理员权限
+    Convert {
+        /// 还原为完整内容，回滚差量存储的迁移前使用
+        #[structopt(long)]
+        full: bool,
+    },
+}
+
+#[derive(StructOpt)]
+enum UserCommand {
+    /// 新建用户，密码从环境变量 `NOTES_PASSWORD` 或标准输入读取
+    Add { nickname: String, email: String },
+}
+
+#[derive(StructOpt)]
+enum TokenCommand {
+    /// 为当前用户生成 Token，需要密码登陆
+    Add,
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if let Err(err) = run(opt) {
+        eprintln!("error[{}]: {}", err.code(), err);
+        std::process::exit(1);
+    }
+}
+
+fn run(opt: Opt) -> Result<(), NoteError> {
+    let config = NotesConfig::load(opt.config.as_deref())?;
+
+    if let Command::Migrate = opt.command {
+        let conn = establish(&config.database_url)?;
+        notes_lib::migrate(&conn)?;
+        println!("Database is up to date");
+        return Ok(());
+    }
+
+    let notes = Notes::connect(config)?;
+    let conn = notes.conn();
+
+    match &opt.command {
+        Command::Migrate => unreachable!(),
+        Command::Post(PostCommand::New {
+            title,
+            parent,
+            file,
+        }) => {
+            let user = login(&notes, &opt)?;
+            let draft = read_markdown(file.as_ref(), "")?;
+            let post = Post::new(None, title.clone(), Some(draft.markdown.clone()));
+            let id = draft.finish(match parent {
+                Some(parent) => post.insert_under(conn, &user, *parent),
+                None => notes.insert_post(&post, &user),
+            })?;
+            println!("{}", id);
+            warn_unresolved(conn, &Post::from_id(conn, id)?)?;
+        }
+        Command::Post(PostCommand::Edit { id, title, file }) => {
+            let user = login(&notes, &opt)?;
+            let origin = Post::from_id(conn, *id)?;
+            let draft = read_markdown(file.as_ref(), origin.get_markdown())?;
+            let title = title
+                .clone()
+                .unwrap_or_else(|| String::from(origin.get_title()));
+            let mut post = Post::new(Some(*id), title, Some(draft.markdown.clone()));
+            post.set_revision(origin.get_revision());
+            draft.finish(post.update(conn, &user))?;
+            warn_unresolved(conn, &post)?;
+        }
+        Command::Post(PostCommand::Show { post }) => {
+            let post = match post.parse::<u32>() {
+                Ok(id) => Post::from_id(conn, id)?,
+                Err(_) => Post::from_slug(conn, post)?,
+            };
+            println!("# {}\n\n{}", post.get_title(), post.get_markdown());
+        }
+        Command::Post(PostCommand::Meta { id }) => {
+            let metadata = Post::from_id(conn, *id)?.metadata()?;
+            let json = serde_json::to_string_pretty(&metadata).map_err(|err| {
+                NoteError::Validation(format!("Failed to format metadata: {}", err))
+            })?;
+            println!("{}", json);
+        }
+        Command::Post(PostCommand::Blame { id }) => {
+            let mut authors = std::collections::HashMap::new();
+            for line in Post::from_id(conn, *id)?.blame(conn)? {
+                let (history, time, author) = match line.revision {
+                    Some(revision) => {
+                        let author = match revision.user_id {
+                            Some(author_id) => authors
+                                .entry(author_id)
+                                .or_insert_with(|| {
+                                    User::from_user_id(author_id, conn)
+                                        .map(|user| String::from(user.get_nickname()))
+                                        .unwrap_or_else(|_| author_id.to_string())
+                                })
+                                .clone(),
+                            None => String::from("-"),
+                        };
+                        (
+                            revision.history_id.to_string(),
+                            format_time(revision.time),
+                            author,
+                        )
+                    }
+                    None => (String::from("-"), String::from("-"), String::from("-")),
+                };
+                println!(
+                    "{}\t{}\t{}\t{:>4}\t{}",
+                    history, time, author, line.number, line.text
+                );
+            }
+        }
+        Command::Post(PostCommand::Delete { id }) => {
+            let user = login(&notes, &opt)?;
+            notes.delete_post(&Post::from_id(conn, *id)?, &user)?;
+        }
+        Command::Post(PostCommand::List {
+            tag,
+            not_tag,
+            meta,
+            offset,
+            limit,
+        }) => {
+            let metadata = meta
+                .iter()
+                .map(|pair| match pair.split_once('=') {
+                    Some((field, value)) => Ok((String::from(field), String::from(value))),
+                    None => Err(NoteError::Validation(format!(
+                        "Metadata filter must be formatted as <field>=<value>: {}",
+                        pair
+                    ))),
+                })
+                .collect::<Result<Vec<(String, String)>, NoteError>>()?;
+            let filter = PostFilter {
+                include_tags: tag.clone(),
+                exclude_tags: not_tag.clone(),
+                metadata,
+                offset: *offset,
+                limit: *limit,
+            };
+            for post in Post::list(conn, &filter)? {
+                println!("{}\t{}", post.get_id(), post.get_title());
+            }
+        }
+        Command::Link { from, to } => {
+            let user = login(&notes, &opt)?;
+            let from_post = Post::from_id(conn, *from)?;
+            let to_post = Post::from_id(conn, *to)?;
+            let mut to_list = Edge::get_to_list(conn, *from)?
+                .iter()
+                .map(|edge| Post::from_id(conn, edge.get_to()))
+                .collect::<Result<Vec<Post>, NoteError>>()?;
+            if to_list.iter().all(|post| post.get_id() != *to) {
+                to_list.push(to_post);
+            }
+            Edge::update_to_list(conn, &user, from_post.get_id(), to_list.iter().collect())?;
+        }
+        Command::Unlink { from, to } => {
+            let user = login(&notes, &opt)?;
+            let edge = Edge::get_to_list(conn, *from)?
+                .into_iter()
+                .find(|edge| edge.get_to() == *to)
+                .ok_or_else(|| NoteError::not_found("edge", format!("{} -> {}", from, to)))?;
+            edge.delete(conn, &user)?;
+        }
+        Command::History(HistoryCommand::List { post_id }) => {
+            for history in History::get_history(*post_id, conn)? {
+                println!("{}\t{}", history.get_id(), format_time(history.get_time()));
+            }
+        }
+        Command::History(HistoryCommand::Show { id }) => {
+            let history = History::from_id(conn, *id)?;
+            println!("{}", history.get_markdown());
+        }
+        Command::History(HistoryCommand::Recent {
+            days,
+            user,
+            root,
+            tag,
+            limit,
+        }) => {
+            let since = match days {
+                Some(days) => {
+                    (chrono::Utc::now().timestamp() - i64::from(*days) * 86400).max(0) as u32
+                }
+                None => 0,
+            };
+            let filter = RecentFilter {
+                user_id: *user,
+                root_id: *root,
+                tag: tag.clone(),
+            };
+            for change in History::recent(conn, since, *limit, &filter)? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    change.history_id,
+                    format_time(change.time),
+                    change.post_id,
+                    change.title
+                );
+            }
+        }
+        Command::History(HistoryCommand::Prune { dry_run }) => {
+            let user = login(&notes, &opt)?;
+            let report = notes.prune_histories(&user, *dry_run)?;
+            if report.dry_run {
+                for history in &report.pruned {
+                    println!(
+                        "{}\t{}\t{}",
+                        history.id,
+                        history.post_id,
+                        format_time(history.time)
+                    );
+                }
+            }
+            println!(
+                "{} {} histories of {} posts, kept {}",
+                match report.dry_run {
+                    true => "Would prune",
+                    false => "Pruned",
+                },
+                report.pruned.len(),
+                report.posts,
+                report.kept
+            );
+        }
+        Command::History(HistoryCommand::Convert { full }) => {
+            let user = login(&notes, &opt)?;
+            let storage = match full {
+                true => HistoryStorage::Full,
+                false => HistoryStorage::Delta,
+            };
+            let report = History::convert(conn, &user, storage)?;
+            println!(
+                "Converted {} histories of {} posts ({} snapshots, {} deltas), {} -> {} bytes",
+                report.histories,
+                report.posts,
+                report.snapshots,
+                report.deltas,
+                report.bytes_before,
+                report.bytes_after
+            );
+        }
+        Command::Tag(TagCommand::List) => {
+            for tag in Tag::list(conn)? {
+                println!("{}\t{}", tag.get_id(), tag.get_name());
+            }
+        }
+        Command::Tag(TagCommand::Rename { name, new_name }) => {
+            let user = login(&notes, &opt)?;
+            Tag::from_name(conn, name)?.rename(conn, &user, new_name)?;
+        }
+        Command::Tag(TagCommand::Merge { name, into }) => {
+            let user = login(&notes, &opt)?;
+            let into = Tag::from_name(conn, into)?;
+            Tag::from_name(conn, name)?.merge(conn, &user, &into)?;
+        }
+        Command::Trash(TrashCommand::List) => {
+            for post in Post::list_trash(conn)? {
+                let deleted_at = format_time(post.get_deleted_at().unwrap_or_default());
+                println!("{}\t{}\t{}", post.get_id(), deleted_at, post.get_title());
+            }
+        }
+        Command::Trash(TrashCommand::Restore { id }) => {
+            let user = login(&notes, &opt)?;
+            Post::from_trash(conn, *id)?.restore(conn, &user)?;
+        }
+        Command::Trash(TrashCommand::Purge { id }) => {
+            let user = login(&notes, &opt)?;
+            match id {
+                Some(id) => Post::from_trash(conn, *id)?.purge(conn, &user)?,
+                None => {
+                    for id in notes.purge_trash(&user)? {
+                        println!("{}", id);
+                    }
+                }
+            }
+        }
+        Command::Export { dir } => {
+            let report =
+                notes_lib::export::export_markdown(conn, notes.config().root_post_id, dir)?;
+            println!(
+                "Exported {} posts ({} links, {} orphans)",
+                report.posts, report.links, report.orphans
+            );
+        }
+        Command::Graph {
+            format,
+            root,
+            depth,
+            links,
+            titles,
+            tags,
+        } => {
+            let options = GraphOptions {
+                root_id: *root,
+                max_depth: *depth,
+                links: *links,
+                titles: *titles,
+                tags: *tags,
+            };
+            print!("{}", Graph::load(conn, &options)?.render(*format));
+        }
+        Command::Feed {
+            format,
+            title,
+            url,
+            post_url,
+            root,
+            tag,
+            limit,
+        } => {
+            let options = FeedOptions {
+                title: title.clone(),
+                feed_url: url.clone(),
+                post_url: post_url.clone(),
+                root_id: *root,
+                tag: tag.clone(),
+                limit: *limit,
+            };
+            print!("{}", notes_lib::feed::render(conn, &options, *format)?);
+        }
+        Command::Backup(BackupCommand::Dump {
+            file,
+            no_credentials,
+        }) => {
+            let options = DumpOptions {
+                credentials: !no_credentials,
+            };
+            let summary = match file {
+                Some(file) => {
+                    let output =
+                        std::fs::File::create(file).map_err(|err| NoteError::from_io(file, err))?;
+                    notes_lib::backup::dump(conn, std::io::BufWriter::new(output), &options)?
+                }
+                None => notes_lib::backup::dump(conn, std::io::stdout().lock(), &options)?,
+            };
+            eprintln!("Dumped {:?} ({})", summary.counts, summary.checksum);
+        }
+        Command::Backup(BackupCommand::Restore { file }) => {
+            let input = std::fs::File::open(file).map_err(|err| NoteError::from_io(file, err))?;
+            let summary = notes_lib::backup::restore(conn, std::io::BufReader::new(input))?;
+            println!("Restored {:?}", summary.counts);
+        }
+        Command::Import {
+            dir,
+            parent,
+            attachments,
+            attachments_url,
+            git_history,
+        } => {
+            let user = login(&notes, &opt)?;
+            let options = ImportOptions {
+                attachments_dir: attachments.clone(),
+                attachments_url: attachments_url.clone(),
+                git_history: *git_history,
+            };
+            let parent = parent.unwrap_or(notes.config().root_post_id);
+            let report = notes_lib::import::import_markdown(conn, &user, parent, dir, &options)?;
+            println!(
+                "Imported {} posts ({} folders, {} edges, {} links, {} attachments, {} histories)",
+                report.posts,
+                report.folders,
+                report.edges,
+                report.links,
+                report.attachments,
+                report.histories
+            );
+            for link in &report.unresolved {
+                eprintln!("unresolved: {}", link);
+            }
+            for title in &report.ambiguous {
+                eprintln!("duplicate title: {}", title);
+            }
+        }
+        Command::User(UserCommand::Add { nickname, email }) => {
+            let password = read_password(&format!("Password for {}: ", nickname))?;
+            let mut user = User::new(None, nickname.clone(), password, email.clone());
+            println!("{}", notes.register(&mut user)?);
+        }
+        Command::Token(TokenCommand::Add) => {
+            let user = login(&notes, &opt)?;
+            println!("{}:{}", user.get_id(), notes.add_token(&user)?);
+        }
+    }
+
+    Ok(())
+}
+
+fn establish(database_url: &str) -> Result<notes_lib::DbConn, NoteError> {
+    use diesel::Connection;
+
+    Ok(notes_lib::DbConn::establish(database_url)?)
+}
+
+fn login(notes: &Notes, opt: &Opt) -> Result<AuthUser, NoteError> {
+    if let Some(token) = &opt.token {
+        let mut parts = token.splitn(2, ':');
+        let user_id = parts.next().and_then(|user_id| user_id.parse::<u32>().ok());
+        return match (user_id, parts.next()) {
+            (Some(user_id), Some(token)) => {
+                notes.login(Auth::Token((user_id, String::from(token))))
+            }
+            _ => Err(NoteError::Validation(String::from(
+                "Token must be formatted as <user_id>:<token>",
+            ))),
+        };
+    }
+
+    match &opt.user {
+        Some(nickname) => {
+            let password = read_password(&format!("Password for {}: ", nickname))?;
+            notes.login(Auth::Password((nickname.clone(), password)))
+        }
+        None => Err(NoteError::AuthError(String::from(
+            "Login required, use --token or --user",
+        ))),
+    }
+}
+
+/// 提示文中找不到对应文章的 wiki 链接
+fn warn_unresolved(conn: &notes_lib::DbConn, post: &Post) -> Result<(), NoteError> {
+    for link in post.resolve_links(conn)?.unresolved {
+        match link {
+            WikiLink::Title(title) => eprintln!("warning: unresolved link [[{}]]", title),
+            WikiLink::Id(id) => eprintln!("warning: unresolved link [[#{}]]", id),
+        }
+    }
+    Ok(())
+}
+
+fn read_password(prompt: &str) -> Result<String, NoteError> {
+    if let Ok(password) = std::env::var("NOTES_PASSWORD") {
+        return Ok(password);
+    }
+
+    eprint!("{}", prompt);
+    std::io::stderr().flush().ok();
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .map_err(|err| NoteError::Validation(format!("Failed to read password: {}", err)))?;
+    Ok(String::from(password.trim_end_matches(&['\r', '\n'][..])))
+}
+
+/// 从文件读取 Markdown，未指定文件时用 `$EDITOR` 编辑 `initial`
+/// 读取的文章内容，来自编辑器时同时记录临时文件
+struct Draft {
+    markdown: String,
+    path: Option<PathBuf>,
+}
+
+impl Draft {
+    /// 保存成功后删除临时文件，失败时保留临时文件，避免丢失编辑的内容
+    fn finish<T>(self, result: Result<T, NoteError>) -> Result<T, NoteError> {
+        if let Some(path) = &self.path {
+            match &result {
+                Ok(_) => {
+                    std::fs::remove_file(path).ok();
+                }
+                Err(_) => eprintln!("Your edit is kept in {}", path.display()),
+            }
+        }
+        result
+    }
+}
+
+/// 从 `file` 读取内容，未给出时用编辑器编辑 `initial`
+fn read_markdown(file: Option<&PathBuf>, initial: &str) -> Result<Draft, NoteError> {
+    if let Some(file) = file {
+        let markdown = std::fs::read_to_string(file).map_err(|err| {
+            NoteError::Validation(format!("Failed to read {}: {}", file.display(), err))
+        })?;
+        return Ok(Draft {
+            markdown,
+            path: None,
+        });
+    }
+
+    let path = create_temp_file(initial)?;
+    let io_error = |err: std::io::Error| {
+        NoteError::Validation(format!("Failed to edit {}: {}", path.display(), err))
+    };
+
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| String::from("vi"));
+    let status = std::process::Command::new(&editor)
+        .arg(&path)
+        .status()
+        .map_err(io_error)?;
+    let markdown = std::fs::read_to_string(&path).map_err(io_error)?;
+
+    match status.success() {
+        true => Ok(Draft {
+            markdown,
+            path: Some(path),
+        }),
+        false => {
+            std::fs::remove_file(&path).ok();
+            Err(NoteError::Validation(format!(
+                "{} exited with {}, aborted",
+                editor, status
+            )))
+        }
+    }
+}
+
+/// 在临时目录中新建只有当前用户可以读写的文件并写入 `content`，文件名随机且不会覆盖已有文件
+fn create_temp_file(content: &str) -> Result<PathBuf, NoteError> {
+    use rand::Rng;
+
+    loop {
+        let path =
+            std::env::temp_dir().join(format!("notes-{:016x}.md", rand::thread_rng().gen::<u64>()));
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let io_error = |err: std::io::Error| {
+            NoteError::Validation(format!("Failed to create {}: {}", path.display(), err))
+        };
+        match options.open(&path) {
+            Ok(mut file) => {
+                file.write_all(content.as_bytes()).map_err(io_error)?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(io_error(err)),
+        }
+    }
+}
+
+fn format_time(time: u32) -> String {
+    use chrono::TimeZone;
+
+    match chrono::Local.timestamp_opt(i64::from(time), 0).single() {
+        Some(time) => time.to_rfc3339(),
+        None => time.to_string(),
+    }
+}
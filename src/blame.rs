@@ -0,0 +1,96 @@
+//! 按行追溯文章内容由哪一条历史记录引入
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+
+/// 引入某一行的历史记录
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Revision {
+    pub history_id: u32,
+    pub time: u32,
+    /// 作者，早于记录作者的历史记录为空
+    pub user_id: Option<u32>,
+}
+
+/// 文章的一行与最后修改它的历史记录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlameLine {
+    /// 行号，从 1 开始
+    pub number: usize,
+    pub text: String,
+    /// 为空时该行不在任何历史记录中
+    pub revision: Option<Revision>,
+}
+
+/// 依次比较相邻的版本，将 `current` 的每一行归属到最后修改它的版本
+///
+/// `versions` 为历史记录与对应的内容，按时间从旧到新排列
+pub fn blame(versions: &[(Revision, &str)], current: &str) -> Vec<BlameLine> {
+    let mut lines: Vec<&str> = vec![];
+    let mut owners: Vec<Option<Revision>> = vec![];
+
+    let steps = versions
+        .iter()
+        .map(|(revision, text)| (Some(*revision), *text))
+        .chain(std::iter::once((None, current)));
+    for (revision, text) in steps {
+        let new = text.lines().collect::<Vec<&str>>();
+        let mut next = Vec::with_capacity(new.len());
+        for op in capture_diff_slices(Algorithm::Myers, &lines, &new) {
+            match op {
+                DiffOp::Equal { old_index, len, .. } => {
+                    next.extend_from_slice(&owners[old_index..old_index + len])
+                }
+                DiffOp::Delete { .. } => (),
+                DiffOp::Insert { new_len, .. } | DiffOp::Replace { new_len, .. } => {
+                    next.resize(next.len() + new_len, revision)
+                }
+            }
+        }
+        lines = new;
+        owners = next;
+    }
+
+    lines
+        .into_iter()
+        .zip(owners)
+        .enumerate()
+        .map(|(index, (text, revision))| BlameLine {
+            number: index + 1,
+            text: String::from(text),
+            revision,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blame, Revision};
+
+    fn revision(history_id: u32) -> Revision {
+        Revision {
+            history_id,
+            time: history_id * 10,
+            user_id: Some(history_id % 2),
+        }
+    }
+
+    #[test]
+    fn lines_are_attributed_to_last_change() {
+        let versions = vec![
+            (revision(1), "# Rust\nownership\nborrowing\n"),
+            (revision(2), "# Rust\nownership\nlifetimes\nborrowing\n"),
+            (revision(3), "# Rust\nOwnership\nlifetimes\nborrowing\n"),
+        ];
+        let lines = blame(&versions, "# Rust\nOwnership\nlifetimes\nborrowing\ntraits");
+
+        let owners = lines
+            .iter()
+            .map(|line| line.revision.map(|revision| revision.history_id))
+            .collect::<Vec<Option<u32>>>();
+        assert_eq!(owners, vec![Some(1), Some(3), Some(2), Some(1), None]);
+        assert_eq!(lines[2].number, 3);
+        assert_eq!(lines[2].text, "lifetimes");
+        assert_eq!(lines[1].revision.unwrap().user_id, Some(1));
+
+        assert!(blame(&[], "").is_empty());
+    }
+}
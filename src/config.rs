@@ -0,0 +1,204 @@
+//! 运行时配置
+use crate::NoteError;
+
+use std::path::Path;
+
+/// 默认的 Token 长度
+pub const DEFAULT_TOKEN_LEN: u32 = 32;
+
+/// bcrypt 允许的 cost 范围
+const MIN_HASH_COST: u32 = 4;
+const MAX_HASH_COST: u32 = 31;
+
+/// 运行时可调整的参数
+///
+/// 可以从 TOML 文件读取，再由环境变量覆盖，未给出的字段使用默认值
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotesConfig {
+    /// 数据库地址，必须给出，环境变量 `NOTES_DATABASE_URL`，也接受 diesel 使用的 `DATABASE_URL`
+    pub database_url: String,
+    /// Token 长度，环境变量 `NOTES_TOKEN_LEN`
+    pub token_len: u32,
+    /// Token 有效期（秒），0 为永不过期，环境变量 `NOTES_TOKEN_LIFETIME`
+    pub token_lifetime: u64,
+    /// 密码哈希的 bcrypt cost，环境变量 `NOTES_HASH_COST`
+    pub hash_cost: u32,
+    /// 新文章默认挂载的根文章，环境变量 `NOTES_ROOT_POST_ID`
+    pub root_post_id: u32,
+    /// 完整保留历史记录的天数，更早的按小时、天与周精简，0 为永久保留，环境变量 `NOTES_HISTORY_RETENTION_DAYS`
+    pub history_retention_days: u32,
+    /// 之后每小时保留一条历史记录的天数，环境变量 `NOTES_HISTORY_HOURLY_DAYS`
+    pub history_hourly_days: u32,
+    /// 再之后每天保留一条历史记录的天数，更早的每周保留一条，环境变量 `NOTES_HISTORY_DAILY_DAYS`
+    pub history_daily_days: u32,
+    /// 回收站中的文章保留天数，超过后可被清理，0 为永久保留，环境变量 `NOTES_TRASH_RETENTION_DAYS`
+    pub trash_retention_days: u32,
+    /// 功能开关
+    pub features: Features,
+}
+
+/// 功能开关，环境变量 `NOTES_FEATURE_<名称>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Features {
+    /// 是否允许注册新用户
+    pub registration: bool,
+    /// 是否允许使用 Token 登陆
+    pub token_auth: bool,
+}
+
+impl Default for NotesConfig {
+    fn default() -> NotesConfig {
+        NotesConfig {
+            database_url: String::new(),
+            token_len: DEFAULT_TOKEN_LEN,
+            token_lifetime: 0,
+            hash_cost: bcrypt::DEFAULT_COST,
+            root_post_id: crate::migration::INDEX_POST_ID,
+            history_retention_days: 0,
+            history_hourly_days: 7,
+            history_daily_days: 90,
+            trash_retention_days: 30,
+            features: Features::default(),
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Features {
+        Features {
+            registration: true,
+            token_auth: true,
+        }
+    }
+}
+
+impl NotesConfig {
+    /// 从 TOML 字符串读取配置
+    pub fn from_toml(content: &str) -> Result<NotesConfig, NoteError> {
+        let config = parse_toml(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 从 TOML 文件读取配置
+    pub fn from_file(path: &Path) -> Result<NotesConfig, NoteError> {
+        let config = read_file(path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 读取配置文件（若给出），再用环境变量覆盖，覆盖后才检查配置
+    pub fn load(path: Option<&Path>) -> Result<NotesConfig, NoteError> {
+        let config = match path {
+            Some(path) => read_file(path)?,
+            None => NotesConfig::default(),
+        };
+        config.with_env()
+    }
+
+    /// 用环境变量覆盖当前配置
+    pub fn with_env(mut self) -> Result<NotesConfig, NoteError> {
+        for name in &["DATABASE_URL", "NOTES_DATABASE_URL"] {
+            if let Ok(url) = std::env::var(name) {
+                self.database_url = url;
+            }
+        }
+        env_override("NOTES_TOKEN_LEN", &mut self.token_len)?;
+        env_override("NOTES_TOKEN_LIFETIME", &mut self.token_lifetime)?;
+        env_override("NOTES_HASH_COST", &mut self.hash_cost)?;
+        env_override("NOTES_ROOT_POST_ID", &mut self.root_post_id)?;
+        env_override(
+            "NOTES_HISTORY_RETENTION_DAYS",
+            &mut self.history_retention_days,
+        )?;
+        env_override("NOTES_HISTORY_HOURLY_DAYS", &mut self.history_hourly_days)?;
+        env_override("NOTES_HISTORY_DAILY_DAYS", &mut self.history_daily_days)?;
+        env_override("NOTES_TRASH_RETENTION_DAYS", &mut self.trash_retention_days)?;
+        env_override(
+            "NOTES_FEATURE_REGISTRATION",
+            &mut self.features.registration,
+        )?;
+        env_override("NOTES_FEATURE_TOKEN_AUTH", &mut self.features.token_auth)?;
+
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// 检查配置是否合法
+    pub fn validate(&self) -> Result<(), NoteError> {
+        if self.database_url.trim().is_empty() {
+            return Err(NoteError::Validation(String::from(
+                "database_url (or NOTES_DATABASE_URL) is required",
+            )));
+        }
+        if self.token_len == 0 {
+            return Err(NoteError::Validation(String::from(
+                "token_len must be positive",
+            )));
+        }
+        if self.hash_cost < MIN_HASH_COST || self.hash_cost > MAX_HASH_COST {
+            return Err(NoteError::Validation(format!(
+                "hash_cost must be between {} and {}",
+                MIN_HASH_COST, MAX_HASH_COST
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn parse_toml(content: &str) -> Result<NotesConfig, NoteError> {
+    toml::from_str::<NotesConfig>(content)
+        .map_err(|err| NoteError::Validation(format!("Failed to parse config: {}", err)))
+}
+
+fn read_file(path: &Path) -> Result<NotesConfig, NoteError> {
+    let content = std::fs::read_to_string(path).map_err(|err| {
+        NoteError::Validation(format!("Failed to read config {}: {}", path.display(), err))
+    })?;
+    parse_toml(&content)
+}
+
+/// 若环境变量 `name` 存在，则解析并覆盖 `value`
+fn env_override<T: std::str::FromStr>(name: &str, value: &mut T) -> Result<(), NoteError> {
+    if let Ok(content) = std::env::var(name) {
+        *value = content.parse::<T>().map_err(|_| {
+            NoteError::Validation(format!("Invalid value of {}: {}", name, content))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NotesConfig;
+
+    #[test]
+    fn toml_fills_missing_fields_with_default() {
+        let config = NotesConfig::from_toml(
+            r#"
+            database_url = "mysql://notes@localhost/notes"
+            token_len = 48
+            root_post_id = 3
+
+            [features]
+            registration = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.token_len, 48);
+        assert_eq!(config.root_post_id, 3);
+        assert_eq!(config.hash_cost, bcrypt::DEFAULT_COST);
+        assert_eq!(config.trash_retention_days, 30);
+        assert!(!config.features.registration);
+        assert!(config.features.token_auth);
+
+        assert!(NotesConfig::from_toml(
+            "database_url = \"mysql://localhost/notes\"\nhash_cost = 1"
+        )
+        .is_err());
+        assert!(NotesConfig::from_toml("token_len = 48").is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! 历史记录的差量存储
+//!
+//! 历史记录保存为完整的快照，或相对于同一篇文章某个快照的差量。
+//! 差量由若干条指令组成：`c <start> <count>` 复制快照中从第 `start` 行开始的 `count` 行，
+//! `i <length>` 之后紧跟插入的 `length` 字节，整体再用 deflate 压缩。
+use crate::NoteError;
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use similar::DiffOp;
+
+use std::io::{Read, Write};
+
+/// 一个快照最多被多少条差量引用，超过后保存新的快照
+pub const SNAPSHOT_INTERVAL: usize = 50;
+
+fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// 生成由 `base` 得到 `text` 的压缩差量
+pub fn encode(base: &str, text: &str) -> Result<Vec<u8>, NoteError> {
+    let old = lines(base);
+    let new = lines(text);
+
+    let mut ops = vec![];
+    for op in similar::capture_diff_slices(similar::Algorithm::Myers, &old, &new) {
+        match op {
+            DiffOp::Equal { old_index, len, .. } => {
+                ops.extend(format!("c {} {}\n", old_index, len).into_bytes())
+            }
+            DiffOp::Delete { .. } => (),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            }
+            | DiffOp::Replace {
+                new_index, new_len, ..
+            } => {
+                let inserted = new[new_index..new_index + new_len].concat();
+                ops.extend(format!("i {}\n", inserted.len()).into_bytes());
+                ops.extend(inserted.into_bytes());
+            }
+        }
+    }
+
+    let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+    encoder
+        .write_all(&ops)
+        .and_then(|_| encoder.finish())
+        .map_err(|err| NoteError::io(String::from("Failed to compress history delta"), err))
+}
+
+/// 将 `encode` 生成的差量应用到 `base` 上
+pub fn apply(base: &str, delta: &[u8]) -> Result<String, NoteError> {
+    let corrupted =
+        |reason: &str| NoteError::Validation(format!("Corrupted history delta: {}", reason));
+
+    let mut ops = vec![];
+    DeflateDecoder::new(delta)
+        .read_to_end(&mut ops)
+        .map_err(|err| NoteError::io(String::from("Failed to decompress history delta"), err))?;
+
+    let old = lines(base);
+    let mut text = String::with_capacity(base.len());
+    let mut rest = ops.as_slice();
+    while !rest.is_empty() {
+        let end = rest
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or_else(|| corrupted("unterminated instruction"))?;
+        let instruction =
+            std::str::from_utf8(&rest[..end]).map_err(|_| corrupted("invalid instruction"))?;
+        rest = &rest[end + 1..];
+
+        let numbers = instruction
+            .get(2..)
+            .unwrap_or("")
+            .split(' ')
+            .map(str::parse::<usize>)
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| corrupted(instruction))?;
+        match (instruction.as_bytes().first(), numbers.as_slice()) {
+            (Some(b'c'), [start, count]) => {
+                let copied = start
+                    .checked_add(*count)
+                    .and_then(|end| old.get(*start..end))
+                    .ok_or_else(|| corrupted("copy out of range"))?;
+                text.extend(copied.iter().copied());
+            }
+            (Some(b'i'), [length]) => {
+                let inserted = rest
+                    .get(..*length)
+                    .ok_or_else(|| corrupted("insert out of range"))?;
+                text.push_str(
+                    std::str::from_utf8(inserted).map_err(|_| corrupted("invalid insert"))?,
+                );
+                rest = &rest[*length..];
+            }
+            _ => return Err(corrupted(instruction)),
+        }
+    }
+
+    Ok(text)
+}
+
+/// 决定如何保存 `text`：返回相对于快照 `base` 的差量，或 `None` 表示保存为新的快照
+///
+/// `deltas` 为快照已被引用的次数，达到 `SNAPSHOT_INTERVAL` 或差量不够小时保存快照
+pub fn choose(base: Option<&str>, deltas: usize, text: &str) -> Result<Option<Vec<u8>>, NoteError> {
+    let base = match base {
+        Some(base) if deltas < SNAPSHOT_INTERVAL => base,
+        _ => return Ok(None),
+    };
+
+    let delta = encode(base, text)?;
+    match delta.len() * 2 < text.len() {
+        true => Ok(Some(delta)),
+        false => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, choose, encode, SNAPSHOT_INTERVAL};
+
+    #[test]
+    fn deltas_round_trip() {
+        let base = "# Rust\n\nownership\nborrowing\nlifetimes\n";
+        for text in [
+            base,
+            "",
+            "# Rust\n\nownership\nlifetimes\ntraits",
+            "# Rust 中文\n\nborrowing\n\nlifetimes\n",
+        ] {
+            let delta = encode(base, text).unwrap();
+            assert_eq!(apply(base, &delta).unwrap(), text);
+        }
+
+        let delta = encode("a\nb\n", "a\nb\nc\n").unwrap();
+        assert!(apply("a\n", &delta).is_err());
+        assert!(apply("a\nb\n", b"not deflate").is_err());
+    }
+
+    #[test]
+    fn snapshots_are_chosen_periodically() {
+        let base = "line\n".repeat(100);
+        let text = format!("{}more\n", base);
+        assert!(choose(None, 0, &text).unwrap().is_none());
+        assert!(choose(Some(&base), 0, &text).unwrap().is_some());
+        assert!(choose(Some(&base), SNAPSHOT_INTERVAL, &text)
+            .unwrap()
+            .is_none());
+        assert!(choose(Some("unrelated\n"), 0, "short\n").unwrap().is_none());
+    }
+}
@@ -0,0 +1,27 @@
+//! 文本差异
+use similar::TextDiff;
+
+/// 上下文的行数
+const CONTEXT_RADIUS: usize = 3;
+
+/// 生成 `old` 到 `new` 的 unified diff，没有差异时为空
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(CONTEXT_RADIUS)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified;
+
+    #[test]
+    fn unified_diff_has_headers_and_hunks() {
+        let diff = unified("a\nb\nc\n", "a\nB\nc\n", "current", "yours");
+        assert!(diff.starts_with("--- current\n+++ yours\n@@ -1,3 +1,3 @@\n"));
+        assert!(diff.contains("-b\n+B\n"));
+        assert_eq!(unified("a\n", "a\n", "current", "yours"), "");
+    }
+}
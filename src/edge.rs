@@ -1,4 +1,4 @@
-//! 文章间的上下级关系
+//! 文章间的上下级关系与链接
 use crate::auth::{AuthDelete, AuthInsert, AuthUser};
 use crate::insert::InsertEdge;
 use crate::raw::RawEdge;
@@ -6,6 +6,57 @@ use crate::{DbConn, NoteError};
 
 use serde::{Deserialize, Serialize};
 
+use std::collections::HashSet;
+
+/// 边的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Relation {
+    /// 上下级关系
+    Child,
+    /// 文中的 wiki 链接
+    Link,
+}
+
+impl Relation {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Relation::Child => 0,
+            Relation::Link => 1,
+        }
+    }
+    pub fn from_u8(relation: u8) -> Relation {
+        match relation {
+            1 => Relation::Link,
+            _ => Relation::Child,
+        }
+    }
+}
+
+/// 子树中的一篇文章
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtreeNode {
+    pub post_id: u32,
+    /// 到根的最短距离
+    pub depth: u32,
+    /// 第一次访问到该文章时经过的上级，根为空
+    pub parent: Option<u32>,
+}
+
+/// 从某篇文章出发，沿上下级关系可以到达的文章
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Subtree {
+    /// 按广度优先的顺序排列，第一项为根
+    pub nodes: Vec<SubtreeNode>,
+    /// 子树中所有文章之间的上下级关系，包括不在生成树上的
+    pub edges: Vec<Edge>,
+}
+
+impl Subtree {
+    pub fn contains(&self, post_id: u32) -> bool {
+        self.nodes.iter().any(|node| node.post_id == post_id)
+    }
+}
+
 /// 以存图的方式存放关系
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Edge {
@@ -14,6 +65,8 @@ pub struct Edge {
     from_post: u32,
     /// 终点
     to_post: u32,
+    /// 种类
+    relation: Relation,
 }
 
 impl Edge {
@@ -26,25 +79,55 @@ impl Edge {
     pub fn get_to(&self) -> u32 {
         self.to_post
     }
+    pub fn get_relation(&self) -> Relation {
+        self.relation
+    }
 
     pub fn new(from_post: u32, to_post: u32) -> Edge {
+        Edge::with_relation(from_post, to_post, Relation::Child)
+    }
+    pub fn with_relation(from_post: u32, to_post: u32, relation: Relation) -> Edge {
         Edge {
             id: 0,
             from_post,
             to_post,
+            relation,
         }
     }
 
-    /// 获取所有起点为 `from_id` 的边
+    pub fn from_id(conn: &DbConn, edge_id: u32) -> Result<Edge, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_edge::dsl::*;
+
+        Ok(Edge::from(
+            &post_edge
+                .filter(id.eq(edge_id))
+                .filter(deleted_at.is_null())
+                .first::<RawEdge>(conn)
+                .map_err(|err| NoteError::from_query("edge", edge_id, err))?,
+        ))
+    }
+
+    /// 获取所有起点为 `from_id` 的上下级关系
     pub fn get_to_list(conn: &DbConn, from_id: u32) -> Result<Vec<Edge>, NoteError> {
+        Edge::get_to_list_of(conn, from_id, Relation::Child)
+    }
+    /// 获取所有起点为 `from_id`，种类为 `kind` 的边
+    pub fn get_to_list_of(
+        conn: &DbConn,
+        from_id: u32,
+        kind: Relation,
+    ) -> Result<Vec<Edge>, NoteError> {
         use crate::diesel::*;
         use crate::schema::post_edge::dsl::*;
 
         let edge_list = post_edge
             .filter(from_post.eq(from_id))
+            .filter(relation.eq(kind.to_u8()))
+            .filter(deleted_at.is_null())
             .load::<RawEdge>(conn)
             .map_err(|err| {
-                NoteError::SQLError(format!("Failed query edge from {}: {}", from_id, err))
+                NoteError::from_diesel(format!("Failed query edge from {}", from_id), err)
             })?
             .iter()
             .map(Edge::from)
@@ -52,76 +135,310 @@ impl Edge {
 
         Ok(edge_list)
     }
-    /// 获取所有起点为 `from_id` 的边
+    /// 将起点为 `from_id` 的上下级关系更新为 `to_list`
     pub fn update_to_list(
         conn: &DbConn,
         auth: &AuthUser,
         from_id: u32,
         to_list: Vec<&crate::post::Post>,
     ) -> Result<(), NoteError> {
-        let origin_to_list = Edge::get_to_list(conn, from_id)?;
+        let to_ids = to_list
+            .iter()
+            .map(|post| post.get_id())
+            .collect::<Vec<u32>>();
+        Edge::update_to_ids(conn, auth, from_id, &to_ids, Relation::Child)
+    }
+    /// 将起点为 `from_id`，种类为 `kind` 的边的终点更新为 `to_ids`
+    pub fn update_to_ids(
+        conn: &DbConn,
+        auth: &AuthUser,
+        from_id: u32,
+        to_ids: &[u32],
+        kind: Relation,
+    ) -> Result<(), NoteError> {
+        let origin_to_list = Edge::get_to_list_of(conn, from_id, kind)?;
         for origin_to in &origin_to_list {
-            if !to_list
+            if !to_ids
                 .iter()
-                .any(|current_to| current_to.get_id() == origin_to.get_to())
+                .any(|current_to| *current_to == origin_to.get_to())
             {
                 origin_to.delete(&conn, &auth)?;
             }
         }
 
-        for current_to in &to_list {
+        for current_to in to_ids {
             if !origin_to_list
                 .iter()
-                .any(|origin_to| current_to.get_id() == origin_to.get_to())
+                .any(|origin_to| *current_to == origin_to.get_to())
             {
-                Edge::new(from_id, current_to.get_id()).insert(&conn, &auth)?;
+                Edge::with_relation(from_id, *current_to, kind).insert(&conn, &auth)?;
             }
         }
 
         Ok(())
     }
-    /// 获取所有终点为 `to_id` 的边
+    /// 获取所有终点为 `to_id` 的上下级关系
     pub fn get_from_list(conn: &DbConn, to_id: u32) -> Result<Vec<Edge>, NoteError> {
+        Edge::get_from_list_of(conn, to_id, Relation::Child)
+    }
+    /// 获取所有终点为 `to_id`，种类为 `kind` 的边
+    pub fn get_from_list_of(
+        conn: &DbConn,
+        to_id: u32,
+        kind: Relation,
+    ) -> Result<Vec<Edge>, NoteError> {
         use crate::diesel::*;
         use crate::schema::post_edge::dsl::*;
 
         let edge_list = post_edge
             .filter(to_post.eq(to_id))
+            .filter(relation.eq(kind.to_u8()))
+            .filter(deleted_at.is_null())
             .load::<RawEdge>(conn)
-            .map_err(|err| NoteError::SQLError(format!("Failed query edge to {}: {}", to_id, err)))?
+            .map_err(|err| NoteError::from_diesel(format!("Failed query edge to {}", to_id), err))?
             .iter()
             .map(Edge::from)
             .collect::<Vec<Edge>>();
 
         Ok(edge_list)
     }
+    /// 将终点为 `to_id` 的上下级关系更新为 `from_list`
     pub fn update_from_list(
         conn: &DbConn,
         auth: &AuthUser,
         to_id: u32,
         from_list: Vec<&crate::post::Post>,
     ) -> Result<(), NoteError> {
-        let origin_from_list = Edge::get_from_list(conn, to_id)?;
+        let from_ids = from_list
+            .iter()
+            .map(|post| post.get_id())
+            .collect::<Vec<u32>>();
+        Edge::update_from_ids(conn, auth, to_id, &from_ids, Relation::Child)
+    }
+    /// 将终点为 `to_id`，种类为 `kind` 的边的起点更新为 `from_ids`
+    pub fn update_from_ids(
+        conn: &DbConn,
+        auth: &AuthUser,
+        to_id: u32,
+        from_ids: &[u32],
+        kind: Relation,
+    ) -> Result<(), NoteError> {
+        let origin_from_list = Edge::get_from_list_of(conn, to_id, kind)?;
         for origin_from in &origin_from_list {
-            if !from_list
+            if !from_ids
                 .iter()
-                .any(|current_from| current_from.get_id() == origin_from.get_from())
+                .any(|current_from| *current_from == origin_from.get_from())
             {
                 origin_from.delete(&conn, &auth)?;
             }
         }
 
-        for current_from in &from_list {
+        for current_from in from_ids {
             if !origin_from_list
                 .iter()
-                .any(|origin_from| current_from.get_id() == origin_from.get_from())
+                .any(|origin_from| *current_from == origin_from.get_from())
             {
-                Edge::new(current_from.get_id(), to_id).insert(&conn, &auth)?;
+                Edge::with_relation(*current_from, to_id, kind).insert(&conn, &auth)?;
             }
         }
 
         Ok(())
     }
+
+    /// 从 `root_id` 出发广度优先遍历上下级关系，`max_depth` 为空时不限深度
+    pub fn get_subtree(
+        conn: &DbConn,
+        root_id: u32,
+        max_depth: Option<u32>,
+    ) -> Result<Subtree, NoteError> {
+        let mut subtree = Subtree {
+            nodes: vec![SubtreeNode {
+                post_id: root_id,
+                depth: 0,
+                parent: None,
+            }],
+            edges: vec![],
+        };
+        let mut visited = HashSet::new();
+        visited.insert(root_id);
+
+        let mut index = 0;
+        while index < subtree.nodes.len() {
+            let (post_id, depth) = (subtree.nodes[index].post_id, subtree.nodes[index].depth);
+            index += 1;
+            if max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                continue;
+            }
+
+            for edge in Edge::get_to_list(conn, post_id)? {
+                if visited.insert(edge.get_to()) {
+                    subtree.nodes.push(SubtreeNode {
+                        post_id: edge.get_to(),
+                        depth: depth + 1,
+                        parent: Some(post_id),
+                    });
+                }
+                subtree.edges.push(edge);
+            }
+        }
+
+        Ok(subtree)
+    }
+
+    /// 将所有以 `post_id` 为起点或终点的边移入回收站
+    pub fn trash_of(
+        conn: &DbConn,
+        user: &AuthUser,
+        post_id: u32,
+        time: u32,
+    ) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_edge::dsl::*;
+
+        user.auth()?;
+
+        diesel::update(
+            post_edge
+                .filter(from_post.eq(post_id).or(to_post.eq(post_id)))
+                .filter(deleted_at.is_null()),
+        )
+        .set(deleted_at.eq(Some(time)))
+        .execute(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to trash edges of {}", post_id), err)
+        })?;
+
+        Ok(())
+    }
+    /// 获取回收站中所有以 `post_id` 为起点或终点的边
+    pub fn get_trashed_of(conn: &DbConn, post_id: u32) -> Result<Vec<Edge>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_edge::dsl::*;
+
+        Ok(post_edge
+            .filter(from_post.eq(post_id).or(to_post.eq(post_id)))
+            .filter(deleted_at.is_not_null())
+            .load::<RawEdge>(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed query trashed edges of {}", post_id), err)
+            })?
+            .iter()
+            .map(Edge::from)
+            .collect::<Vec<Edge>>())
+    }
+    /// 将回收站中的边恢复
+    pub fn restore(&self, conn: &DbConn, user: &AuthUser) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_edge::dsl::*;
+
+        user.auth()?;
+
+        diesel::update(post_edge.filter(id.eq(self.id)))
+            .set(deleted_at.eq(None::<u32>))
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to restore edge{:?}", self), err)
+            })?;
+
+        Ok(())
+    }
+    /// 彻底删除所有以 `post_id` 为起点或终点的边，包括回收站中的边
+    pub fn purge_of(conn: &DbConn, user: &AuthUser, post_id: u32) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_edge::dsl::*;
+
+        user.auth()?;
+
+        diesel::delete(post_edge.filter(from_post.eq(post_id).or(to_post.eq(post_id))))
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to delete edges of {}", post_id), err)
+            })?;
+
+        Ok(())
+    }
+    /// 删除 `trashed_id` 时插入当前的边，把它的下级文章改挂到 `from_post` 下
+    pub fn insert_reattached(
+        &self,
+        conn: &DbConn,
+        user: &AuthUser,
+        trashed_id: u32,
+    ) -> Result<u32, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_edge;
+
+        user.auth()?;
+
+        diesel::insert_into(post_edge::table)
+            .values((
+                InsertEdge::from(&*self),
+                post_edge::reattached_from.eq(Some(trashed_id)),
+            ))
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to insert edge{:?}", self), err)
+            })?;
+
+        Ok(crate::get_last_insert_rowid(conn)?)
+    }
+    /// 删除不再需要的改挂的边：改挂前的上级文章与下级文章之间的边已经恢复
+    ///
+    /// 只检查因删除 `post_id` 而插入的边，以及改挂 `post_id` 的边
+    pub fn remove_reattached(
+        conn: &DbConn,
+        user: &AuthUser,
+        post_id: u32,
+    ) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_edge::dsl::*;
+
+        user.auth()?;
+
+        let reattached = post_edge
+            .filter(
+                reattached_from
+                    .eq(post_id)
+                    .or(to_post.eq(post_id).and(reattached_from.is_not_null())),
+            )
+            .filter(deleted_at.is_null())
+            .load::<RawEdge>(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed query reattached edges of {}", post_id), err)
+            })?;
+        for edge in &reattached {
+            let origin = match edge.reattached_from {
+                Some(origin) => origin,
+                None => continue,
+            };
+            let restored = Edge::get_to_list(conn, origin)?
+                .iter()
+                .any(|active| active.get_to() == edge.to_post);
+            if restored {
+                Edge::from(edge).delete(conn, user)?;
+            }
+        }
+
+        Ok(())
+    }
+    /// `post_id` 被彻底删除后，因删除它而改挂的边成为普通的边
+    pub fn keep_reattached(conn: &DbConn, user: &AuthUser, post_id: u32) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_edge::dsl::*;
+
+        user.auth()?;
+
+        diesel::update(post_edge.filter(reattached_from.eq(post_id)))
+            .set(reattached_from.eq(None::<u32>))
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(
+                    format!("Failed to keep reattached edges of {}", post_id),
+                    err,
+                )
+            })?;
+
+        Ok(())
+    }
 }
 
 impl AuthInsert for Edge {
@@ -135,7 +452,7 @@ impl AuthInsert for Edge {
             .values(InsertEdge::from(&*self))
             .execute(conn)
             .map_err(|err| {
-                NoteError::SQLError(format!("Failed to delete edge{:?}: {}", self, err))
+                NoteError::from_diesel(format!("Failed to insert edge{:?}", self), err)
             })?;
 
         Ok(crate::get_last_insert_rowid(conn)?)
@@ -152,7 +469,7 @@ impl AuthDelete for Edge {
         diesel::delete(table.filter(id.eq(self.get_id())))
             .execute(conn)
             .map_err(|err| {
-                NoteError::SQLError(format!("Failed to delete edge{:?}: {}", self, err))
+                NoteError::from_diesel(format!("Failed to delete edge{:?}", self), err)
             })?;
 
         Ok(())
@@ -165,6 +482,7 @@ impl From<&RawEdge> for Edge {
             id: edge.id,
             from_post: edge.from_post,
             to_post: edge.to_post,
+            relation: Relation::from_u8(edge.relation),
         }
     }
 }
@@ -173,6 +491,7 @@ impl From<&Edge> for InsertEdge {
         InsertEdge {
             from_post: edge.from_post,
             to_post: edge.to_post,
+            relation: edge.relation.to_u8(),
         }
     }
 }
@@ -0,0 +1,272 @@
+//! 错误类型
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// 错误的来源，仅在进程内传递，不参与序列化
+pub type ErrorSource = Arc<dyn Error + Send + Sync>;
+
+/// Note 错误类型
+#[derive(Debug, Serialize, Deserialize)]
+pub enum NoteError {
+    /// 无法找到记录
+    NotFound { entity: String, id: String },
+    /// 与已有数据冲突，例如违反唯一约束或编辑时版本号已过期
+    Conflict {
+        message: String,
+        /// 编辑冲突时，数据库中当前的版本号
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        current_revision: Option<u32>,
+        /// 编辑冲突时，提交的内容与当前内容的差异
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        diff: Option<String>,
+        #[serde(skip)]
+        source: Option<ErrorSource>,
+    },
+    /// 输入不合法
+    Validation(String),
+    /// 数据库不可用，例如无法连接
+    Unavailable {
+        message: String,
+        #[serde(skip)]
+        source: Option<ErrorSource>,
+    },
+    /// 无法找到用户
+    UserNotFound(String),
+    /// 认证失败
+    AuthError(String),
+    /// 没有权限
+    NoPermission(String),
+    /// 读写文件失败
+    Io {
+        message: String,
+        #[serde(skip)]
+        source: Option<ErrorSource>,
+    },
+    /// 其他 SQL 错误
+    SQLError {
+        message: String,
+        #[serde(skip)]
+        source: Option<ErrorSource>,
+    },
+}
+
+impl NoteError {
+    pub fn not_found<T: fmt::Display>(entity: &str, id: T) -> NoteError {
+        NoteError::NotFound {
+            entity: String::from(entity),
+            id: id.to_string(),
+        }
+    }
+
+    /// 与已有数据冲突
+    pub fn conflict(message: String) -> NoteError {
+        NoteError::Conflict {
+            message,
+            current_revision: None,
+            diff: None,
+            source: None,
+        }
+    }
+
+    /// 将 diesel 的错误按种类展开，`message` 描述当前进行的操作
+    pub fn from_diesel(message: String, err: diesel::result::Error) -> NoteError {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        let message = format!("{}: {}", message, err);
+        match err {
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)
+            | Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)
+            | Error::DatabaseError(DatabaseErrorKind::SerializationFailure, _) => {
+                NoteError::Conflict {
+                    message,
+                    current_revision: None,
+                    diff: None,
+                    source: Some(Arc::new(err)),
+                }
+            }
+            Error::DatabaseError(DatabaseErrorKind::UnableToSendCommand, _) => {
+                NoteError::Unavailable {
+                    message,
+                    source: Some(Arc::new(err)),
+                }
+            }
+            _ => NoteError::SQLError {
+                message,
+                source: Some(Arc::new(err)),
+            },
+        }
+    }
+
+    /// 读写 `path` 失败
+    pub fn from_io(path: &std::path::Path, err: std::io::Error) -> NoteError {
+        NoteError::io(format!("Failed to access {}", path.display()), err)
+    }
+
+    /// 读写失败，`message` 描述当前进行的操作
+    pub fn io(message: String, err: std::io::Error) -> NoteError {
+        NoteError::Io {
+            message: format!("{}: {}", message, err),
+            source: Some(Arc::new(err)),
+        }
+    }
+
+    /// 查询单条记录时使用，找不到记录时返回 `NotFound`
+    pub fn from_query<T: fmt::Display>(
+        entity: &str,
+        id: T,
+        err: diesel::result::Error,
+    ) -> NoteError {
+        match err {
+            diesel::result::Error::NotFound => NoteError::not_found(entity, id),
+            err => NoteError::from_diesel(format!("Failed to query {} {}", entity, id), err),
+        }
+    }
+
+    /// 在错误信息前加上 `context`，保留错误的种类，`NotFound` 保持不变
+    pub fn with_context(self, context: &str) -> NoteError {
+        let prefix = |message: String| format!("{}: {}", context, message);
+        match self {
+            NoteError::NotFound { .. } => self,
+            NoteError::Conflict {
+                message,
+                current_revision,
+                diff,
+                source,
+            } => NoteError::Conflict {
+                message: prefix(message),
+                current_revision,
+                diff,
+                source,
+            },
+            NoteError::Validation(message) => NoteError::Validation(prefix(message)),
+            NoteError::Unavailable { message, source } => NoteError::Unavailable {
+                message: prefix(message),
+                source,
+            },
+            NoteError::UserNotFound(message) => NoteError::UserNotFound(prefix(message)),
+            NoteError::AuthError(message) => NoteError::AuthError(prefix(message)),
+            NoteError::NoPermission(message) => NoteError::NoPermission(prefix(message)),
+            NoteError::Io { message, source } => NoteError::Io {
+                message: prefix(message),
+                source,
+            },
+            NoteError::SQLError { message, source } => NoteError::SQLError {
+                message: prefix(message),
+                source,
+            },
+        }
+    }
+
+    /// 稳定的机器可读错误码
+    pub fn code(&self) -> &'static str {
+        match self {
+            NoteError::NotFound { .. } => "not_found",
+            NoteError::Conflict { .. } => "conflict",
+            NoteError::Validation(_) => "validation",
+            NoteError::Unavailable { .. } => "unavailable",
+            NoteError::UserNotFound(_) => "user_not_found",
+            NoteError::AuthError(_) => "auth_failed",
+            NoteError::NoPermission(_) => "no_permission",
+            NoteError::Io { .. } => "io_error",
+            NoteError::SQLError { .. } => "sql_error",
+        }
+    }
+
+    /// 对应的 HTTP 状态码
+    pub fn http_status(&self) -> u16 {
+        match self {
+            NoteError::NotFound { .. } => 404,
+            NoteError::Conflict { .. } => 409,
+            NoteError::Validation(_) => 422,
+            NoteError::Unavailable { .. } => 503,
+            NoteError::UserNotFound(_) => 404,
+            NoteError::AuthError(_) => 401,
+            NoteError::NoPermission(_) => 403,
+            NoteError::Io { .. } => 500,
+            NoteError::SQLError { .. } => 500,
+        }
+    }
+}
+
+impl fmt::Display for NoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NoteError::NotFound { entity, id } => write!(f, "Not found {} {}", entity, id),
+            NoteError::Conflict { message, .. }
+            | NoteError::Unavailable { message, .. }
+            | NoteError::Io { message, .. }
+            | NoteError::SQLError { message, .. } => write!(f, "{}", message),
+            NoteError::Validation(message)
+            | NoteError::UserNotFound(message)
+            | NoteError::AuthError(message)
+            | NoteError::NoPermission(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for NoteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NoteError::Conflict { source, .. }
+            | NoteError::Unavailable { source, .. }
+            | NoteError::Io { source, .. }
+            | NoteError::SQLError { source, .. } => source
+                .as_ref()
+                .map(|source| &**source as &(dyn Error + 'static)),
+            _ => None,
+        }
+    }
+}
+
+impl From<diesel_migrations::RunMigrationsError> for NoteError {
+    fn from(err: diesel_migrations::RunMigrationsError) -> NoteError {
+        NoteError::SQLError {
+            message: format!("Failed to run migrations: {}", err),
+            source: Some(Arc::new(err)),
+        }
+    }
+}
+
+/// 用于 `Connection::transaction`，事务中的查询应尽量使用 `from_diesel` 说明操作
+impl From<diesel::result::Error> for NoteError {
+    fn from(err: diesel::result::Error) -> NoteError {
+        NoteError::from_diesel(String::from("Transaction failed"), err)
+    }
+}
+
+impl From<diesel::ConnectionError> for NoteError {
+    fn from(err: diesel::ConnectionError) -> NoteError {
+        NoteError::Unavailable {
+            message: format!("Failed to connect database: {}", err),
+            source: Some(Arc::new(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NoteError;
+    use std::error::Error;
+
+    #[test]
+    fn diesel_errors_are_classified() {
+        let err = NoteError::from_query("post", 3, diesel::result::Error::NotFound);
+        assert_eq!(err.code(), "not_found");
+        assert_eq!(err.http_status(), 404);
+        assert_eq!(err.to_string(), "Not found post 3");
+
+        let err = NoteError::from_diesel(
+            String::from("Failed to insert post"),
+            diesel::result::Error::RollbackTransaction,
+        );
+        assert_eq!(err.code(), "sql_error");
+        assert!(err.source().is_some());
+
+        let err =
+            NoteError::Validation(String::from("bad tag")).with_context("Failed to retag post 2");
+        assert_eq!(err.code(), "validation");
+        assert_eq!(err.to_string(), "Failed to retag post 2: bad tag");
+    }
+}
@@ -0,0 +1,223 @@
+//! 将所有文章导出为 Markdown 目录
+//!
+//! 目录结构与上下级关系一致：根文章为 `index.md`，其余文章为 `<slug>.md`，
+//! 它的下级文章放在同名目录 `<slug>/` 中。有多个上级的文章只在第一次访问到的位置导出，
+//! 其余位置放一个指向它的链接文件，根文章无法到达的文章放在 `_orphans/` 中
+use crate::edge::Edge;
+use crate::post::{Post, PostFilter};
+use crate::tag::Tag;
+use crate::{DbConn, NoteError};
+
+use serde_json::Value;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// 根文章的文件名
+const INDEX_FILE: &str = "index.md";
+/// 根文章无法到达的文章所在的目录
+const ORPHANS_DIR: &str = "_orphans";
+
+/// 导出结果
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ExportReport {
+    /// 导出的文章数
+    pub posts: usize,
+    /// 链接文件数
+    pub links: usize,
+    /// 根文章无法到达的文章数
+    pub orphans: usize,
+}
+
+/// 从 `root_id` 出发，将所有文章导出到目录 `dir`
+pub fn export_markdown(conn: &DbConn, root_id: u32, dir: &Path) -> Result<ExportReport, NoteError> {
+    let mut exporter = Exporter {
+        conn,
+        dir,
+        paths: HashMap::new(),
+        report: ExportReport::default(),
+    };
+
+    let root = Post::from_id(conn, root_id)?;
+    exporter.export_tree(&root, Path::new(INDEX_FILE), PathBuf::new())?;
+
+    let all = Post::list(conn, &PostFilter::default())?;
+    // 先导出没有上级的文章，再导出只在环中的文章
+    let mut orphans = vec![];
+    for post in &all {
+        if !exporter.paths.contains_key(&post.get_id()) {
+            let has_parent = !Edge::get_from_list(conn, post.get_id())?.is_empty();
+            orphans.push((has_parent, post));
+        }
+    }
+    orphans.sort_by_key(|(has_parent, post)| (*has_parent, post.get_id()));
+    for (_, post) in orphans {
+        if exporter.paths.contains_key(&post.get_id()) {
+            continue;
+        }
+        let file = exporter.file_name(post, Path::new(ORPHANS_DIR));
+        let children_dir = file.with_extension("");
+        let before = exporter.paths.len();
+        exporter.export_tree(post, &file, children_dir)?;
+        exporter.report.orphans += exporter.paths.len() - before;
+    }
+
+    Ok(exporter.report)
+}
+
+struct Exporter<'a> {
+    conn: &'a DbConn,
+    dir: &'a Path,
+    /// 已导出的文章及其相对路径
+    paths: HashMap<u32, PathBuf>,
+    report: ExportReport,
+}
+
+impl Exporter<'_> {
+    /// 导出 `root` 及其可以到达的文章，`root` 写到 `file`，下级文章放在 `children_dir`
+    fn export_tree(
+        &mut self,
+        root: &Post,
+        file: &Path,
+        children_dir: PathBuf,
+    ) -> Result<(), NoteError> {
+        self.write_post(root, file)?;
+        let mut queue = vec![(root.get_id(), children_dir)];
+
+        let mut index = 0;
+        while index < queue.len() {
+            let (post_id, dir) = queue[index].clone();
+            index += 1;
+
+            let mut used = HashSet::new();
+            for edge in Edge::get_to_list(self.conn, post_id)? {
+                let child = Post::from_id(self.conn, edge.get_to())?;
+                let file = self.file_name(&child, &dir);
+                if !used.insert(file.clone()) {
+                    continue;
+                }
+                match self.paths.get(&child.get_id()).cloned() {
+                    Some(target) => self.write_link(&child, &file, &target)?,
+                    None => {
+                        self.write_post(&child, &file)?;
+                        queue.push((child.get_id(), file.with_extension("")));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 文章在 `dir` 中的文件名，避开根文章与孤立文章目录的名字
+    fn file_name(&self, post: &Post, dir: &Path) -> PathBuf {
+        let name = match post.get_slug() {
+            Some(slug) if !slug.is_empty() => String::from(slug),
+            _ => format!("post-{}", post.get_id()),
+        };
+        let name = match name.as_str() {
+            "index" | ORPHANS_DIR => format!("{}-{}", name, post.get_id()),
+            _ => name,
+        };
+        dir.join(format!("{}.md", name))
+    }
+
+    fn write_post(&mut self, post: &Post, file: &Path) -> Result<(), NoteError> {
+        let mut metadata = post.metadata()?;
+        metadata.tags = Tag::get_post_tags(self.conn, post.get_id())?
+            .iter()
+            .map(|tag| String::from(tag.get_name()))
+            .collect();
+        metadata
+            .extra
+            .insert(String::from("id"), Value::from(post.get_id()));
+        metadata
+            .extra
+            .insert(String::from("title"), Value::from(post.get_title()));
+        for (key, time) in &[
+            ("created_at", post.get_created_at()),
+            ("updated_at", post.get_updated_at()),
+        ] {
+            if let Some(time) = format_time(*time) {
+                metadata.extra.insert(String::from(*key), Value::from(time));
+            }
+        }
+
+        let content = crate::front_matter::with_metadata(post.get_markdown(), &metadata)?;
+        self.write(file, &content)?;
+        self.paths.insert(post.get_id(), file.to_path_buf());
+        self.report.posts += 1;
+        Ok(())
+    }
+
+    /// 在 `file` 写入指向 `target` 的链接
+    fn write_link(&mut self, post: &Post, file: &Path, target: &Path) -> Result<(), NoteError> {
+        let parent = file.parent().unwrap_or_else(|| Path::new(""));
+        let link = relative(parent, target);
+        let markdown = format!(
+            "---\nid: {}\nlink: {}\n---\n[{}]({})\n",
+            post.get_id(),
+            link,
+            post.get_title().replace(']', "\\]"),
+            link.replace(' ', "%20")
+        );
+        self.write(file, &markdown)?;
+        self.report.links += 1;
+        Ok(())
+    }
+
+    fn write(&self, file: &Path, content: &str) -> Result<(), NoteError> {
+        let path = self.dir.join(file);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| NoteError::from_io(parent, err))?;
+        }
+        std::fs::write(&path, content).map_err(|err| NoteError::from_io(&path, err))
+    }
+}
+
+/// 从目录 `from` 到文件 `to` 的相对路径，使用 `/` 分隔
+fn relative(from: &Path, to: &Path) -> String {
+    let from = from.components().collect::<Vec<Component>>();
+    let to = to.components().collect::<Vec<Component>>();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(from, to)| from == to)
+        .count();
+
+    let mut parts = vec![String::from(".."); from.len() - common];
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+fn format_time(time: u32) -> Option<String> {
+    use chrono::TimeZone;
+
+    match time {
+        0 => None,
+        time => chrono::Utc
+            .timestamp_opt(i64::from(time), 0)
+            .single()
+            .map(|time| time.to_rfc3339()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::relative;
+    use std::path::Path;
+
+    #[test]
+    fn relative_paths_between_files() {
+        assert_eq!(relative(Path::new(""), Path::new("a/b.md")), "a/b.md");
+        assert_eq!(relative(Path::new("a/b"), Path::new("a/c.md")), "../c.md");
+        assert_eq!(
+            relative(Path::new("x"), Path::new("_orphans/y.md")),
+            "../_orphans/y.md"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! 由历史记录生成最近修改的 Atom 与 RSS 订阅
+use crate::history::{History, RecentFilter};
+use crate::render::escape_html;
+use crate::user::User;
+use crate::{DbConn, NoteError};
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// 条目中的差异最多保留的行数
+const MAX_DIFF_LINES: usize = 200;
+
+/// 订阅格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+impl FromStr for FeedFormat {
+    type Err = NoteError;
+
+    fn from_str(format: &str) -> Result<FeedFormat, NoteError> {
+        match format.to_lowercase().as_str() {
+            "atom" => Ok(FeedFormat::Atom),
+            "rss" => Ok(FeedFormat::Rss),
+            _ => Err(NoteError::Validation(format!(
+                "Unknown feed format {}, expected atom or rss",
+                format
+            ))),
+        }
+    }
+}
+
+/// 订阅选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedOptions {
+    /// 订阅的标题
+    pub title: String,
+    /// 订阅自身的地址，同时作为 Atom 的 id
+    pub feed_url: String,
+    /// 文章地址的模板，`{id}` 与 `{slug}` 会被替换为文章的 id 与 slug，没有 slug 的文章使用 id
+    pub post_url: String,
+    /// 只包含从该文章出发沿上下级关系可以到达的文章
+    pub root_id: Option<u32>,
+    /// 只包含带有该标签的文章
+    pub tag: Option<String>,
+    /// 最多包含的条目数
+    pub limit: i64,
+}
+
+/// 订阅中的一条修改
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedEntry {
+    pub history_id: u32,
+    pub post_id: u32,
+    pub title: String,
+    pub url: String,
+    pub time: u32,
+    pub author: Option<String>,
+    /// 是否为文章的第一条历史记录
+    pub created: bool,
+    /// 增加的行数
+    pub added: usize,
+    /// 删除的行数
+    pub removed: usize,
+    /// 与上一条历史记录的差异，过长时会被截断
+    pub diff: String,
+}
+
+/// 按 `options` 读取最近的修改，按时间从新到旧排列
+pub fn entries(conn: &DbConn, options: &FeedOptions) -> Result<Vec<FeedEntry>, NoteError> {
+    let filter = RecentFilter {
+        user_id: None,
+        root_id: options.root_id,
+        tag: options.tag.clone(),
+    };
+
+    let mut users = HashMap::new();
+    let mut result = vec![];
+    for change in History::recent(conn, 0, options.limit, &filter)? {
+        let author = match change.user_id {
+            Some(author_id) => users
+                .entry(author_id)
+                .or_insert_with(|| {
+                    User::from_user_id(author_id, conn)
+                        .map(|user| String::from(user.get_nickname()))
+                        .ok()
+                })
+                .clone(),
+            None => None,
+        };
+
+        let history = History::from_id(conn, change.history_id)?;
+        let previous = history.previous(conn)?;
+        let old = previous
+            .as_ref()
+            .map(|previous| previous.get_markdown())
+            .unwrap_or("");
+        let (diff, added, removed) = summarize(old, history.get_markdown());
+
+        result.push(FeedEntry {
+            history_id: change.history_id,
+            post_id: change.post_id,
+            url: post_url(&options.post_url, change.post_id, change.slug.as_deref()),
+            title: change.title,
+            time: change.time,
+            author,
+            created: previous.is_none(),
+            added,
+            removed,
+            diff,
+        });
+    }
+
+    Ok(result)
+}
+
+/// 生成订阅
+pub fn render(
+    conn: &DbConn,
+    options: &FeedOptions,
+    format: FeedFormat,
+) -> Result<String, NoteError> {
+    let entries = entries(conn, options)?;
+    Ok(match format {
+        FeedFormat::Atom => atom(options, &entries),
+        FeedFormat::Rss => rss(options, &entries),
+    })
+}
+
+/// 按模板生成文章地址，没有 slug 时 `{slug}` 也替换为 id
+fn post_url(template: &str, post_id: u32, slug: Option<&str>) -> String {
+    let id = post_id.to_string();
+    let slug = slug
+        .map(crate::slug::encode)
+        .filter(|slug| !slug.is_empty())
+        .unwrap_or_else(|| id.clone());
+    template.replace("{id}", &id).replace("{slug}", &slug)
+}
+
+/// 差异与增删的行数，差异超过 `MAX_DIFF_LINES` 行时截断
+fn summarize(old: &str, new: &str) -> (String, usize, usize) {
+    let diff = crate::diff::unified(old, new, "before", "after");
+    let mut added = 0;
+    let mut removed = 0;
+    for line in diff.lines() {
+        if line.starts_with('+') && !line.starts_with("+++") {
+            added += 1;
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            removed += 1;
+        }
+    }
+
+    let total = diff.lines().count();
+    let diff = match total > MAX_DIFF_LINES {
+        true => format!(
+            "{}\n… {} more lines\n",
+            diff.lines()
+                .take(MAX_DIFF_LINES)
+                .collect::<Vec<&str>>()
+                .join("\n"),
+            total - MAX_DIFF_LINES
+        ),
+        false => diff,
+    };
+    (diff, added, removed)
+}
+
+fn entry_title(entry: &FeedEntry) -> String {
+    match entry.created {
+        true => format!("Created: {}", entry.title),
+        false => format!("Updated: {}", entry.title),
+    }
+}
+
+fn entry_content(entry: &FeedEntry) -> String {
+    format!(
+        "<p>+{} −{} lines</p><pre>{}</pre>",
+        entry.added,
+        entry.removed,
+        escape_html(&entry.diff)
+    )
+}
+
+fn timestamp(time: u32) -> chrono::DateTime<chrono::Utc> {
+    use chrono::TimeZone;
+
+    chrono::Utc
+        .timestamp_opt(i64::from(time), 0)
+        .single()
+        .unwrap_or_else(|| chrono::Utc.timestamp_opt(0, 0).unwrap())
+}
+
+/// Atom 1.0
+pub fn atom(options: &FeedOptions, entries: &[FeedEntry]) -> String {
+    let updated = entries.first().map(|entry| entry.time).unwrap_or(0);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <title>{}</title>\n<id>{}</id>\n<link rel=\"self\" href=\"{}\"/>\n<updated>{}</updated>\n",
+        escape_html(&options.title),
+        escape_html(&options.feed_url),
+        escape_html(&options.feed_url),
+        timestamp(updated).to_rfc3339()
+    );
+    for entry in entries {
+        xml.push_str(&format!(
+            "<entry>\n<title>{}</title>\n<id>{}#history-{}</id>\n<link href=\"{}\"/>\n\
+             <updated>{}</updated>\n<author><name>{}</name></author>\n\
+             <content type=\"html\">{}</content>\n</entry>\n",
+            escape_html(&entry_title(entry)),
+            escape_html(&options.feed_url),
+            entry.history_id,
+            escape_html(&entry.url),
+            timestamp(entry.time).to_rfc3339(),
+            escape_html(entry.author.as_deref().unwrap_or("unknown")),
+            escape_html(&entry_content(entry))
+        ));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// RSS 2.0
+pub fn rss(options: &FeedOptions, entries: &[FeedEntry]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\">\n<channel>\n\
+         <title>{}</title>\n<link>{}</link>\n<description>{}</description>\n",
+        escape_html(&options.title),
+        escape_html(&options.feed_url),
+        escape_html(&options.title)
+    );
+    for entry in entries {
+        xml.push_str(&format!(
+            "<item>\n<title>{}</title>\n<link>{}</link>\n\
+             <guid isPermaLink=\"false\">{}#history-{}</guid>\n<pubDate>{}</pubDate>\n",
+            escape_html(&entry_title(entry)),
+            escape_html(&entry.url),
+            escape_html(&options.feed_url),
+            entry.history_id,
+            timestamp(entry.time).to_rfc2822()
+        ));
+        if let Some(author) = &entry.author {
+            xml.push_str(&format!(
+                "<dc:creator xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{}</dc:creator>\n",
+                escape_html(author)
+            ));
+        }
+        xml.push_str(&format!(
+            "<description>{}</description>\n</item>\n",
+            escape_html(&entry_content(entry))
+        ));
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{atom, post_url, rss, summarize, FeedEntry, FeedOptions};
+
+    #[test]
+    fn entries_are_rendered_with_escaped_diffs() {
+        let (diff, added, removed) = summarize("a\n<b>\n", "a\n<c>\nd\n");
+        assert_eq!((added, removed), (2, 1));
+
+        let options = FeedOptions {
+            title: String::from("Notes & more"),
+            feed_url: String::from("https://notes.example/feed.xml"),
+            post_url: String::from("https://notes.example/{slug}.html"),
+            root_id: None,
+            tag: None,
+            limit: 20,
+        };
+        let entries = vec![FeedEntry {
+            history_id: 7,
+            post_id: 2,
+            title: String::from("Rust"),
+            url: String::from("https://notes.example/rust.html"),
+            time: 0,
+            author: Some(String::from("alice")),
+            created: false,
+            added,
+            removed,
+            diff,
+        }];
+
+        let xml = atom(&options, &entries);
+        assert!(xml.contains("<title>Notes &amp; more</title>"));
+        assert!(xml.contains("<id>https://notes.example/feed.xml#history-7</id>"));
+        assert!(xml.contains("<title>Updated: Rust</title>"));
+        assert!(xml.contains("+&amp;lt;c&amp;gt;"));
+        assert!(xml.contains("<updated>1970-01-01T00:00:00+00:00</updated>"));
+
+        let xml = rss(&options, &entries);
+        assert!(xml.contains("<pubDate>Thu, 1 Jan 1970 00:00:00 +0000</pubDate>"));
+        assert!(xml.contains(">alice</dc:creator>"));
+
+        let template = "https://notes.example/{slug}.html?id={id}";
+        assert_eq!(
+            post_url(template, 2, Some("rust")),
+            "https://notes.example/rust.html?id=2"
+        );
+        assert_eq!(
+            post_url(template, 2, None),
+            "https://notes.example/2.html?id=2"
+        );
+        assert_eq!(
+            post_url(template, 2, Some("")),
+            "https://notes.example/2.html?id=2"
+        );
+    }
+}
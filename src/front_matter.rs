@@ -0,0 +1,442 @@
+//! 文章开头的 front matter，支持 YAML（`---`）与 TOML（`+++`）
+use crate::raw::RawPostMetadata;
+use crate::{DbConn, NoteError};
+
+use serde_json::{Map, Value};
+
+use std::collections::BTreeMap;
+
+/// 单独存放、可用于查询的字段，`aliases` 中的每一项存为一条 `alias`
+pub const INDEXED_FIELDS: &[&str] = &["status", "alias", "date"];
+
+/// 存放的字段值的最大字符数，与数据库中的长度限制一致
+const MAX_VALUE_LEN: usize = 191;
+
+/// front matter 的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    fn delimiter(self) -> &'static str {
+        match self {
+            Format::Yaml => "---",
+            Format::Toml => "+++",
+        }
+    }
+}
+
+/// 文章的元数据
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub status: Option<String>,
+    /// 文章的别名，`aliases` 或 `alias`
+    pub aliases: Vec<String>,
+    pub date: Option<String>,
+    /// `tags` 或 `tag`
+    pub tags: Vec<String>,
+    /// 其余字段
+    pub extra: BTreeMap<String, Value>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self == &Metadata::default()
+    }
+
+    /// 需要单独存放的字段，`(字段名, 值)`
+    pub fn indexed(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![];
+        if let Some(status) = &self.status {
+            fields.push(("status", status.clone()));
+        }
+        for alias in &self.aliases {
+            fields.push(("alias", alias.clone()));
+        }
+        if let Some(date) = &self.date {
+            fields.push(("date", date.clone()));
+        }
+        fields
+    }
+
+    fn from_map(mut map: Map<String, Value>) -> Metadata {
+        let mut take = |keys: &[&str]| keys.iter().find_map(|key| map.remove(*key));
+
+        let status = take(&["status"]).and_then(scalar);
+        let aliases = take(&["aliases", "alias"]).map(list).unwrap_or_default();
+        let date = take(&["date"]).and_then(scalar);
+        let tags = take(&["tags", "tag"]).map(list).unwrap_or_default();
+
+        Metadata {
+            status,
+            aliases,
+            date,
+            tags,
+            extra: map.into_iter().collect(),
+        }
+    }
+
+    fn to_map(&self) -> Map<String, Value> {
+        let mut map = Map::new();
+        if let Some(status) = &self.status {
+            map.insert(String::from("status"), Value::from(status.as_str()));
+        }
+        if !self.aliases.is_empty() {
+            map.insert(String::from("aliases"), Value::from(self.aliases.clone()));
+        }
+        if let Some(date) = &self.date {
+            map.insert(String::from("date"), Value::from(date.as_str()));
+        }
+        if !self.tags.is_empty() {
+            map.insert(String::from("tags"), Value::from(self.tags.clone()));
+        }
+        for (key, value) in &self.extra {
+            map.insert(key.clone(), value.clone());
+        }
+        map
+    }
+}
+
+/// 文中的 front matter
+pub struct FrontMatter<'a> {
+    pub format: Format,
+    /// 分隔线之间的内容
+    pub content: &'a str,
+    /// 内容在文中的起始位置
+    pub content_start: usize,
+    /// 正文在文中的起始位置
+    pub body_start: usize,
+}
+
+/// 找出文章开头的 front matter
+///
+/// 没有结束的分隔线，或内容无法解析为映射时不视为 front matter，而是正文，
+/// 例如以分隔线（`---`）开头的文章
+pub fn split(markdown: &str) -> Option<FrontMatter<'_>> {
+    find(markdown).filter(|front_matter| parse_block(front_matter).is_ok())
+}
+
+/// 按分隔线找出文章开头的块，不检查其中的内容
+fn find(markdown: &str) -> Option<FrontMatter<'_>> {
+    let mut lines = markdown.split_inclusive('\n');
+    let first = lines.next()?;
+    let format = match first.trim_end() {
+        "---" => Format::Yaml,
+        "+++" => Format::Toml,
+        _ => return None,
+    };
+
+    let content_start = first.len();
+    let mut offset = content_start;
+    for line in lines {
+        let content = line.trim_end();
+        if content == format.delimiter() || (format == Format::Yaml && content == "...") {
+            return Some(FrontMatter {
+                format,
+                content: &markdown[content_start..offset],
+                content_start,
+                body_start: offset + line.len(),
+            });
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+/// 去掉 front matter 后的正文
+pub fn body(markdown: &str) -> &str {
+    match split(markdown) {
+        Some(front_matter) => &markdown[front_matter.body_start..],
+        None => markdown,
+    }
+}
+
+/// 解析文中的元数据，没有 front matter 时返回空的元数据
+///
+/// 开头的块无法解析为映射时视为正文，同样返回空的元数据
+pub fn parse(markdown: &str) -> Result<Metadata, NoteError> {
+    match find(markdown).map(|front_matter| parse_block(&front_matter)) {
+        Some(Ok(metadata)) => Ok(metadata),
+        Some(Err(NoteError::Validation(_))) | None => Ok(Metadata::default()),
+        Some(Err(err)) => Err(err),
+    }
+}
+
+/// 解析 front matter 中的元数据，内容必须是映射
+fn parse_block(front_matter: &FrontMatter) -> Result<Metadata, NoteError> {
+    let value = match front_matter.format {
+        Format::Yaml => serde_yaml::from_str::<Value>(front_matter.content)
+            .map_err(|err| NoteError::Validation(format!("Invalid YAML front matter: {}", err)))?,
+        Format::Toml => toml::from_str::<toml::Value>(front_matter.content)
+            .map(toml_to_json)
+            .map_err(|err| NoteError::Validation(format!("Invalid TOML front matter: {}", err)))?,
+    };
+
+    match value {
+        Value::Object(map) => Ok(Metadata::from_map(map)),
+        Value::Null => Ok(Metadata::default()),
+        _ => Err(NoteError::Validation(String::from(
+            "Front matter must be a mapping",
+        ))),
+    }
+}
+
+/// 将文中的 front matter 替换为 `metadata`，保留原有格式，原来没有 front matter 时使用 YAML
+///
+/// `metadata` 为空时去掉 front matter
+pub fn with_metadata(markdown: &str, metadata: &Metadata) -> Result<String, NoteError> {
+    let (format, body) = match split(markdown) {
+        Some(front_matter) => (front_matter.format, &markdown[front_matter.body_start..]),
+        None => (Format::Yaml, markdown),
+    };
+    if metadata.is_empty() {
+        return Ok(String::from(body));
+    }
+
+    let map = metadata.to_map();
+    let content = match format {
+        Format::Yaml => serde_yaml::to_string(&map)
+            .map(|content| String::from(content.trim_start_matches("---\n")))
+            .map_err(|err| NoteError::Validation(format!("Invalid metadata: {}", err)))?,
+        Format::Toml => toml::to_string(&json_to_toml(Value::Object(map)))
+            .map_err(|err| NoteError::Validation(format!("Invalid metadata: {}", err)))?,
+    };
+
+    let mut result = String::from(format.delimiter());
+    result.push('\n');
+    result.push_str(&content);
+    if !content.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(format.delimiter());
+    result.push('\n');
+    result.push_str(body);
+    Ok(result)
+}
+
+/// 标量转为字符串，其余类型忽略
+fn scalar(value: Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// 列表中的标量，或以逗号分隔的字符串
+fn list(value: Value) -> Vec<String> {
+    match value {
+        Value::Array(values) => values.into_iter().filter_map(scalar).collect(),
+        Value::String(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+        value => scalar(value).into_iter().collect(),
+    }
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(value) => Value::from(value),
+        toml::Value::Integer(value) => Value::from(value),
+        toml::Value::Float(value) => Value::from(value),
+        toml::Value::Boolean(value) => Value::from(value),
+        toml::Value::Datetime(value) => Value::from(value.to_string()),
+        toml::Value::Array(values) => Value::Array(values.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// 转为 TOML，能解析为日期的字符串写为 TOML 日期，表与数组中的 `null` 会被丢弃
+fn json_to_toml(value: Value) -> toml::Value {
+    match value {
+        Value::Null => toml::Value::String(String::new()),
+        Value::Bool(value) => toml::Value::Boolean(value),
+        Value::Number(value) => match value.as_i64() {
+            Some(value) => toml::Value::Integer(value),
+            None => toml::Value::Float(value.as_f64().unwrap_or_default()),
+        },
+        Value::String(value) => match value.parse::<toml::value::Datetime>() {
+            Ok(datetime) => toml::Value::Datetime(datetime),
+            Err(_) => toml::Value::String(value),
+        },
+        Value::Array(values) => toml::Value::Array(
+            values
+                .into_iter()
+                .filter(|value| !value.is_null())
+                .map(json_to_toml)
+                .collect(),
+        ),
+        Value::Object(map) => toml::Value::Table(
+            map.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, json_to_toml(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// 按 `markdown` 中的元数据更新 `post_id` 单独存放的字段
+pub fn sync(conn: &DbConn, post_id: u32, markdown: &str) -> Result<Metadata, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::post_metadata;
+
+    let metadata = parse(markdown)?;
+
+    remove_post(conn, post_id)?;
+    let mut rows = Vec::<RawPostMetadata>::new();
+    for (name, value) in metadata.indexed() {
+        let value = value.chars().take(MAX_VALUE_LEN).collect::<String>();
+        // 主键按数据库的排序规则比较，大小写不同的值视为重复
+        if !rows
+            .iter()
+            .any(|row| row.name == name && row.value.to_lowercase() == value.to_lowercase())
+        {
+            rows.push(RawPostMetadata {
+                post_id,
+                name: String::from(name),
+                value,
+            });
+        }
+    }
+    // 排序规则还可能忽略重音等差异，重复的行直接跳过
+    if !rows.is_empty() {
+        diesel::insert_or_ignore_into(post_metadata::table)
+            .values(&rows)
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to insert metadata of {}", post_id), err)
+            })?;
+    }
+
+    Ok(metadata)
+}
+
+/// 删除 `post_id` 单独存放的字段
+pub fn remove_post(conn: &DbConn, query_id: u32) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::post_metadata::dsl::*;
+
+    diesel::delete(post_metadata.filter(post_id.eq(query_id)))
+        .execute(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to delete metadata of {}", query_id), err)
+        })?;
+    Ok(())
+}
+
+/// 获取字段 `field` 为 `field_value` 的文章的 id，不包括回收站中的文章
+pub fn find_posts(conn: &DbConn, field: &str, field_value: &str) -> Result<Vec<u32>, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::{post_metadata, posts};
+
+    post_metadata::table
+        .inner_join(posts::table.on(posts::id.eq(post_metadata::post_id)))
+        .select(post_metadata::post_id)
+        .filter(post_metadata::name.eq(field))
+        .filter(post_metadata::value.eq(field_value))
+        .filter(posts::deleted_at.is_null())
+        .order(post_metadata::post_id.asc())
+        .load::<u32>(conn)
+        .map_err(|err| NoteError::from_diesel(format!("Failed to query posts by {}", field), err))
+}
+
+/// 没有任何字段记录时，从已有文章中解析，忽略无法解析的 front matter
+pub fn backfill(conn: &DbConn) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::{post_metadata, posts};
+
+    let count = post_metadata::table
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query metadata"), err))?;
+    if count > 0 {
+        return Ok(());
+    }
+
+    let contents = posts::table
+        .select((posts::id, posts::markdown))
+        .load::<(u32, Option<String>)>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query posts"), err))?;
+    for (post_id, markdown) in contents {
+        match sync(conn, post_id, markdown.as_deref().unwrap_or("")) {
+            Ok(_) | Err(NoteError::Validation(_)) => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{body, parse, split, with_metadata};
+
+    #[test]
+    fn yaml_and_toml_are_parsed() {
+        let yaml =
+            parse("---\nstatus: draft\naliases: [A, B]\ndate: 2021-02-26\nweight: 3\n---\nBody")
+                .unwrap();
+        assert_eq!(yaml.status.as_deref(), Some("draft"));
+        assert_eq!(yaml.aliases, vec!["A", "B"]);
+        assert_eq!(yaml.date.as_deref(), Some("2021-02-26"));
+        assert_eq!(yaml.extra["weight"], 3);
+
+        let toml = parse("+++\nalias = \"C\"\ndate = 2021-02-26\ntags = [\"x\"]\n+++\n").unwrap();
+        assert_eq!(toml.aliases, vec!["C"]);
+        assert_eq!(toml.date.as_deref(), Some("2021-02-26"));
+        assert_eq!(toml.tags, vec!["x"]);
+
+        // 无法解析为映射的块是正文，例如以分隔线开头的文章
+        assert!(parse("---\n- a\n---\n").unwrap().is_empty());
+        let thematic = "---\nSome paragraph\nspanning lines\n---\nMore";
+        assert!(parse(thematic).unwrap().is_empty());
+        assert_eq!(body(thematic), thematic);
+        assert_eq!(
+            body("---\nSome paragraph\n---\n"),
+            "---\nSome paragraph\n---\n"
+        );
+        assert!(parse("---\nno end").unwrap().is_empty());
+        assert_eq!(body("+++\na = 1\n+++\nBody"), "Body");
+    }
+
+    #[test]
+    fn metadata_round_trips() {
+        for markdown in &[
+            "---\ntitle: x\n---\nBody\n",
+            "+++\ntitle = \"x\"\n+++\nBody\n",
+        ] {
+            let mut metadata = parse(markdown).unwrap();
+            metadata.status = Some(String::from("done"));
+            metadata.date = Some(String::from("2021-02-26"));
+
+            let updated = with_metadata(markdown, &metadata).unwrap();
+            assert_eq!(parse(&updated).unwrap(), metadata);
+            assert_eq!(body(&updated), "Body\n");
+            assert_eq!(
+                split(&updated).unwrap().format,
+                split(markdown).unwrap().format
+            );
+        }
+
+        let metadata = parse("---\nstatus: a\n---\n").unwrap();
+        assert!(with_metadata("Body", &metadata)
+            .unwrap()
+            .starts_with("---\nstatus: a\n---\n"));
+        assert_eq!(
+            with_metadata("---\nstatus: a\n---\nBody", &Default::default()).unwrap(),
+            "Body"
+        );
+    }
+}
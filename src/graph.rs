@@ -0,0 +1,336 @@
+//! 将文章之间的关系导出为 Graphviz DOT、GraphML 或 node-link JSON
+use crate::edge::{Edge, Relation};
+use crate::post::{Post, PostFilter};
+use crate::render::escape_html;
+use crate::tag::Tag;
+use crate::{DbConn, NoteError};
+
+use serde_json::{json, Value};
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// 导出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// GraphML，可以导入 Gephi
+    GraphMl,
+    /// node-link JSON，与 networkx 和 d3 使用的格式相同
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = NoteError;
+
+    fn from_str(format: &str) -> Result<GraphFormat, NoteError> {
+        match format.to_lowercase().as_str() {
+            "dot" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(NoteError::Validation(format!(
+                "Unknown graph format {}, expected dot, graphml or json",
+                format
+            ))),
+        }
+    }
+}
+
+/// 导出选项
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GraphOptions {
+    /// 只导出从该文章出发沿上下级关系可以到达的文章，为空时导出所有文章
+    pub root_id: Option<u32>,
+    /// 与根文章的最大距离，仅在指定根文章时有效
+    pub max_depth: Option<u32>,
+    /// 是否包含文中的链接，不包含时只导出上下级关系
+    pub links: bool,
+    /// 是否带上标题
+    pub titles: bool,
+    /// 是否带上标签
+    pub tags: bool,
+}
+
+/// 图中的一篇文章
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphNode {
+    pub id: u32,
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// 图中的一条边
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphEdge {
+    pub source: u32,
+    pub target: u32,
+    pub relation: Relation,
+}
+
+/// 文章之间的关系图
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Graph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+fn relation_name(relation: Relation) -> &'static str {
+    match relation {
+        Relation::Child => "child",
+        Relation::Link => "link",
+    }
+}
+
+/// 转义 DOT 字符串中的字符
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Graph {
+    /// 按 `options` 读取文章与边，回收站中的文章与边不会被导出
+    pub fn load(conn: &DbConn, options: &GraphOptions) -> Result<Graph, NoteError> {
+        let mut posts = vec![];
+        let mut edges = vec![];
+        match options.root_id {
+            Some(root_id) => {
+                let subtree = Edge::get_subtree(conn, root_id, options.max_depth)?;
+                for node in &subtree.nodes {
+                    match Post::from_id(conn, node.post_id) {
+                        Ok(post) => posts.push(post),
+                        Err(NoteError::NotFound { .. }) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                edges = subtree.edges;
+            }
+            None => {
+                posts = Post::list(conn, &PostFilter::default())?;
+                for post in &posts {
+                    edges.extend(Edge::get_to_list(conn, post.get_id())?);
+                }
+            }
+        }
+        if options.links {
+            for post in &posts {
+                edges.extend(Edge::get_to_list_of(conn, post.get_id(), Relation::Link)?);
+            }
+        }
+
+        let ids = posts
+            .iter()
+            .map(|post| post.get_id())
+            .collect::<HashSet<u32>>();
+        let mut graph = Graph::default();
+        for post in &posts {
+            let tags = match options.tags {
+                true => Tag::get_post_tags(conn, post.get_id())?
+                    .iter()
+                    .map(|tag| String::from(tag.get_name()))
+                    .collect(),
+                false => vec![],
+            };
+            graph.nodes.push(GraphNode {
+                id: post.get_id(),
+                title: match options.titles {
+                    true => Some(String::from(post.get_title())),
+                    false => None,
+                },
+                tags,
+            });
+        }
+        for edge in edges {
+            let edge = GraphEdge {
+                source: edge.get_from(),
+                target: edge.get_to(),
+                relation: edge.get_relation(),
+            };
+            if ids.contains(&edge.source)
+                && ids.contains(&edge.target)
+                && !graph.edges.contains(&edge)
+            {
+                graph.edges.push(edge);
+            }
+        }
+
+        Ok(graph)
+    }
+
+    /// 按 `format` 输出
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::GraphMl => self.to_graphml(),
+            GraphFormat::Json => self.to_json().to_string(),
+        }
+    }
+
+    /// Graphviz DOT，链接使用虚线
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph notes {\n");
+        for node in &self.nodes {
+            let mut attributes = vec![];
+            if let Some(title) = &node.title {
+                attributes.push(format!("label=\"{}\"", escape_dot(title)));
+            }
+            if !node.tags.is_empty() {
+                attributes.push(format!("tags=\"{}\"", escape_dot(&node.tags.join(","))));
+            }
+            match attributes.is_empty() {
+                true => dot.push_str(&format!("  \"{}\";\n", node.id)),
+                false => dot.push_str(&format!("  \"{}\" [{}];\n", node.id, attributes.join(", "))),
+            }
+        }
+        for edge in &self.edges {
+            match edge.relation {
+                Relation::Child => {
+                    dot.push_str(&format!("  \"{}\" -> \"{}\";\n", edge.source, edge.target))
+                }
+                Relation::Link => dot.push_str(&format!(
+                    "  \"{}\" -> \"{}\" [style=dashed, relation=\"link\"];\n",
+                    edge.source, edge.target
+                )),
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// GraphML，标题、标签与边的种类作为属性
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+             <key id=\"title\" for=\"node\" attr.name=\"title\" attr.type=\"string\"/>\n  \
+             <key id=\"tags\" for=\"node\" attr.name=\"tags\" attr.type=\"string\"/>\n  \
+             <key id=\"relation\" for=\"edge\" attr.name=\"relation\" attr.type=\"string\"/>\n  \
+             <graph id=\"notes\" edgedefault=\"directed\">\n",
+        );
+        for node in &self.nodes {
+            xml.push_str(&format!("    <node id=\"n{}\">", node.id));
+            if let Some(title) = &node.title {
+                xml.push_str(&format!(
+                    "<data key=\"title\">{}</data>",
+                    escape_html(title)
+                ));
+            }
+            if !node.tags.is_empty() {
+                xml.push_str(&format!(
+                    "<data key=\"tags\">{}</data>",
+                    escape_html(&node.tags.join(","))
+                ));
+            }
+            xml.push_str("</node>\n");
+        }
+        for (index, edge) in self.edges.iter().enumerate() {
+            xml.push_str(&format!(
+                "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\"><data key=\"relation\">{}</data></edge>\n",
+                index,
+                edge.source,
+                edge.target,
+                relation_name(edge.relation)
+            ));
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+
+    /// node-link JSON
+    pub fn to_json(&self) -> Value {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let mut value = json!({ "id": node.id });
+                if let Some(title) = &node.title {
+                    value["title"] = json!(title);
+                }
+                if !node.tags.is_empty() {
+                    value["tags"] = json!(node.tags);
+                }
+                value
+            })
+            .collect::<Vec<Value>>();
+        let links = self
+            .edges
+            .iter()
+            .map(|edge| {
+                json!({
+                    "source": edge.source,
+                    "target": edge.target,
+                    "relation": relation_name(edge.relation),
+                })
+            })
+            .collect::<Vec<Value>>();
+
+        json!({
+            "directed": true,
+            "multigraph": false,
+            "graph": {},
+            "nodes": nodes,
+            "links": links,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Graph, GraphEdge, GraphFormat, GraphNode};
+    use crate::edge::Relation;
+
+    fn graph() -> Graph {
+        Graph {
+            nodes: vec![
+                GraphNode {
+                    id: 1,
+                    title: Some(String::from("Index \"home\"")),
+                    tags: vec![],
+                },
+                GraphNode {
+                    id: 2,
+                    title: None,
+                    tags: vec![String::from("rust"), String::from("db")],
+                },
+            ],
+            edges: vec![
+                GraphEdge {
+                    source: 1,
+                    target: 2,
+                    relation: Relation::Child,
+                },
+                GraphEdge {
+                    source: 2,
+                    target: 1,
+                    relation: Relation::Link,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn graph_is_rendered_in_each_format() {
+        let graph = graph();
+        assert_eq!(
+            graph.to_dot(),
+            "digraph notes {\n  \"1\" [label=\"Index \\\"home\\\"\"];\n  \"2\" [tags=\"rust,db\"];\n  \
+             \"1\" -> \"2\";\n  \"2\" -> \"1\" [style=dashed, relation=\"link\"];\n}\n"
+        );
+
+        let xml = graph.to_graphml();
+        assert!(xml
+            .contains("<node id=\"n1\"><data key=\"title\">Index &quot;home&quot;</data></node>"));
+        assert!(xml.contains(
+            "<edge id=\"e1\" source=\"n2\" target=\"n1\"><data key=\"relation\">link</data></edge>"
+        ));
+
+        let json = graph.to_json();
+        assert_eq!(json["nodes"][1]["tags"][0], "rust");
+        assert_eq!(json["links"][0]["relation"], "child");
+        assert_eq!(
+            "GraphML".parse::<GraphFormat>().ok(),
+            Some(GraphFormat::GraphMl)
+        );
+    }
+}
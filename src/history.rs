@@ -1,11 +1,14 @@
 //! 历史记录
 use crate::auth::{AuthDelete, AuthInsert, AuthUser};
+use crate::edge::Edge;
 use crate::raw::RawHistory;
 use crate::DbConn;
 use crate::NoteError;
 
 use crate::insert::InsertHistory;
 
+use std::collections::HashMap;
+
 /// 文章的历史记录
 #[derive(Serialize, Deserialize)]
 pub struct History {
@@ -15,15 +18,71 @@ pub struct History {
     time: u32,
     /// 这次历史记录的时间
     markdown: Option<String>,
+    /// 作者，早于记录作者的历史记录为空
+    user_id: Option<u32>,
+}
+
+/// 最近修改的筛选条件
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecentFilter {
+    /// 只包含该用户的修改
+    pub user_id: Option<u32>,
+    /// 只包含从该文章出发沿上下级关系可以到达的文章
+    pub root_id: Option<u32>,
+    /// 只包含带有该标签的文章
+    pub tag: Option<String>,
+}
+
+/// 时间线上的一次修改
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentChange {
+    pub history_id: u32,
+    pub post_id: u32,
+    /// 文章当前的标题
+    pub title: String,
+    pub slug: Option<String>,
+    pub time: u32,
+    pub user_id: Option<u32>,
+}
+
+/// 历史记录的保存方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HistoryStorage {
+    /// 定期保存完整快照，其余保存为相对于快照的压缩差量
+    Delta,
+    /// 每条历史记录都保存完整内容
+    Full,
+}
+
+/// 转换历史记录保存方式的结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConvertReport {
+    pub posts: usize,
+    pub histories: usize,
+    pub snapshots: usize,
+    pub deltas: usize,
+    /// 转换前内容与差量占用的字节数
+    pub bytes_before: usize,
+    /// 转换后内容与差量占用的字节数
+    pub bytes_after: usize,
+}
+
+fn stored_size(history: &RawHistory) -> usize {
+    history.markdown.as_ref().map_or(0, String::len) + history.delta.as_ref().map_or(0, Vec::len)
 }
 
 impl History {
     pub fn new(post_id: u32, post_mardown: &str) -> History {
+        History::with_time(post_id, post_mardown, chrono::Utc::now().timestamp() as u32)
+    }
+    /// 指定时间的历史记录，用于导入已有的记录
+    pub fn with_time(post_id: u32, post_mardown: &str, time: u32) -> History {
         History {
             id: 0,
             post_id,
-            time: chrono::Utc::now().timestamp() as u32,
+            time,
             markdown: Some(String::from(post_mardown)),
+            user_id: None,
         }
     }
 
@@ -36,6 +95,9 @@ impl History {
     pub fn get_time(&self) -> u32 {
         self.time
     }
+    pub fn get_user_id(&self) -> Option<u32> {
+        self.user_id
+    }
     pub fn get_markdown(&self) -> &str {
         match &self.markdown {
             Some(markdown) => markdown,
@@ -43,38 +105,313 @@ impl History {
         }
     }
 
+    /// 渲染为过滤过的 HTML
+    pub fn render_html(&self) -> String {
+        crate::render::render_html(self.get_markdown())
+    }
+
     pub fn from_id(conn: &DbConn, query_id: u32) -> Result<History, NoteError> {
         use crate::diesel::*;
         use crate::schema::histories::dsl::*;
 
-        let history = History::from(
-            &histories
-                .filter(id.eq(query_id))
-                .first::<RawHistory>(conn)
+        let history = histories
+            .filter(id.eq(query_id))
+            .first::<RawHistory>(conn)
+            .map_err(|err| NoteError::from_query("history", query_id, err))?;
+
+        History::from_raw(conn, vec![history])?
+            .pop()
+            .ok_or_else(|| NoteError::not_found("history", query_id))
+    }
+
+    /// 还原数据库中的历史记录，差量会被应用到它所基于的快照上
+    pub(crate) fn from_raw(
+        conn: &DbConn,
+        raw_list: Vec<RawHistory>,
+    ) -> Result<Vec<History>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::histories::dsl::*;
+
+        let mut snapshots = HashMap::new();
+        let mut missing = vec![];
+        for history in &raw_list {
+            match history.base_id {
+                None => {
+                    snapshots.insert(history.id, history.markdown.clone().unwrap_or_default());
+                }
+                Some(base) => missing.push(base),
+            }
+        }
+        missing.retain(|base| !snapshots.contains_key(base));
+        if !missing.is_empty() {
+            let loaded = histories
+                .filter(id.eq_any(missing))
+                .filter(base_id.is_null())
+                .select((id, markdown))
+                .load::<(u32, Option<String>)>(conn)
                 .map_err(|err| {
-                    NoteError::SQLError(format!("Failed query history of {}: {}", query_id, err))
-                })?,
-        );
+                    NoteError::from_diesel(String::from("Failed to query history snapshots"), err)
+                })?;
+            for (snapshot_id, snapshot) in loaded {
+                snapshots.insert(snapshot_id, snapshot.unwrap_or_default());
+            }
+        }
 
-        Ok(history)
+        raw_list
+            .into_iter()
+            .map(|history| {
+                let content = match history.base_id {
+                    Some(base) => {
+                        let snapshot = snapshots
+                            .get(&base)
+                            .ok_or_else(|| NoteError::not_found("history snapshot", base))?;
+                        Some(crate::delta::apply(
+                            snapshot,
+                            history.delta.as_deref().unwrap_or_default(),
+                        )?)
+                    }
+                    None => history.markdown,
+                };
+                Ok(History {
+                    id: history.id,
+                    post_id: history.post_id,
+                    time: history.time,
+                    markdown: content,
+                    user_id: history.user_id,
+                })
+            })
+            .collect()
     }
 
-    /// 获取某篇文章的历史记录列表
+    /// 获取某篇文章的历史记录列表，按时间排序
     pub fn get_history(query_id: u32, conn: &DbConn) -> Result<Vec<History>, NoteError> {
         use crate::diesel::*;
         use crate::schema::histories::dsl::*;
         let history_list = histories
             .filter(post_id.eq(query_id))
+            .order((time.asc(), id.asc()))
             .load::<RawHistory>(conn)
             .map_err(|err| {
-                NoteError::SQLError(format!("Failed query history of {}: {}", query_id, err))
+                NoteError::from_diesel(format!("Failed query history of {}", query_id), err)
+            })?;
+
+        History::from_raw(conn, history_list)
+    }
+
+    /// 同一篇文章的上一条历史记录
+    pub fn previous(&self, conn: &DbConn) -> Result<Option<History>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::histories::dsl::*;
+
+        let previous = histories
+            .filter(post_id.eq(self.post_id))
+            .filter(
+                time.lt(self.time)
+                    .or(time.eq(self.time).and(id.lt(self.id))),
+            )
+            .order((time.desc(), id.desc()))
+            .first::<RawHistory>(conn)
+            .optional()
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to query history before {}", self.id), err)
+            })?;
+
+        match previous {
+            Some(previous) => Ok(History::from_raw(conn, vec![previous])?.pop()),
+            None => Ok(None),
+        }
+    }
+
+    /// 所有文章自 `since` 起的修改，按时间从新到旧排列，最多 `limit` 条
+    ///
+    /// 回收站中的文章不会出现在结果中，按不存在的标签筛选时结果为空
+    pub fn recent(
+        conn: &DbConn,
+        since: u32,
+        limit: i64,
+        filter: &RecentFilter,
+    ) -> Result<Vec<RecentChange>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::{histories, posts};
+
+        let mut scope: Option<Vec<u32>> = None;
+        if let Some(root_id) = filter.root_id {
+            let subtree = Edge::get_subtree(conn, root_id, None)?;
+            scope = Some(subtree.nodes.iter().map(|node| node.post_id).collect());
+        }
+        if let Some(tag) = &filter.tag {
+            // 不存在的标签没有文章，时间线为空
+            let tagged = match crate::tag::Tag::from_name(conn, tag) {
+                Ok(tag) => tag.get_post_ids(conn)?,
+                Err(NoteError::NotFound { .. }) => vec![],
+                Err(err) => return Err(err),
+            };
+            scope = Some(match scope {
+                Some(ids) => ids
+                    .into_iter()
+                    .filter(|post| tagged.contains(post))
+                    .collect(),
+                None => tagged,
+            });
+        }
+        if scope.as_ref().is_some_and(Vec::is_empty) {
+            return Ok(vec![]);
+        }
+
+        let mut query = histories::table
+            .inner_join(posts::table.on(posts::id.eq(histories::post_id)))
+            .filter(posts::deleted_at.is_null())
+            .filter(histories::time.ge(since))
+            .select((
+                histories::id,
+                histories::post_id,
+                posts::title,
+                posts::slug,
+                histories::time,
+                histories::user_id,
+            ))
+            .into_boxed();
+        if let Some(author_id) = filter.user_id {
+            query = query.filter(histories::user_id.eq(author_id));
+        }
+        if let Some(ids) = scope {
+            query = query.filter(histories::post_id.eq_any(ids));
+        }
+
+        let changes = query
+            .order((histories::time.desc(), histories::id.desc()))
+            .limit(limit)
+            .load::<(u32, u32, String, Option<String>, u32, Option<u32>)>(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(String::from("Failed to query recent changes"), err)
             })?
-            .iter()
-            .map(History::from)
-            .collect::<Vec<History>>();
+            .into_iter()
+            .map(
+                |(history_id, post_id, title, slug, time, user_id)| RecentChange {
+                    history_id,
+                    post_id,
+                    title,
+                    slug,
+                    time,
+                    user_id,
+                },
+            )
+            .collect();
+
+        Ok(changes)
+    }
+
+    /// 将所有历史记录转换为 `storage` 指定的保存方式，需要管理员权限
+    ///
+    /// 用于压缩引入差量存储之前的历史记录，或在回滚迁移前还原为完整内容
+    pub fn convert(
+        conn: &DbConn,
+        user: &AuthUser,
+        storage: HistoryStorage,
+    ) -> Result<ConvertReport, NoteError> {
+        if !user.is_admin() {
+            return Err(NoteError::NoPermission(String::from(
+                "Only admin can convert histories",
+            )));
+        }
+        convert(conn, storage)
+    }
+}
+
+/// 逐篇文章转换历史记录的保存方式，每篇文章在一个事务中完成
+pub(crate) fn convert(conn: &DbConn, storage: HistoryStorage) -> Result<ConvertReport, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::histories::dsl::*;
+
+    let post_ids = histories
+        .select(post_id)
+        .distinct()
+        .order(post_id.asc())
+        .load::<u32>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query histories"), err))?;
 
-        Ok(history_list)
+    let mut report = ConvertReport::default();
+    for query_id in post_ids {
+        conn.transaction::<_, NoteError, _>(|| {
+            let raw_list = load_raw(conn, query_id)?;
+            let history_list = History::from_raw(conn, raw_list.clone())?;
+            store(conn, &raw_list, &history_list, storage, &mut report)
+        })?;
     }
+
+    Ok(report)
+}
+
+/// 读取某篇文章在数据库中的历史记录，按 id 排序
+pub(crate) fn load_raw(conn: &DbConn, query_id: u32) -> Result<Vec<RawHistory>, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::histories::dsl::*;
+
+    histories
+        .filter(post_id.eq(query_id))
+        .order(id.asc())
+        .load::<RawHistory>(conn)
+        .map_err(|err| NoteError::from_diesel(format!("Failed query history of {}", query_id), err))
+}
+
+/// 按 `storage` 重新保存同一篇文章的历史记录
+///
+/// `raw_list` 为数据库中的记录，`history_list` 为对应的已还原的内容，两者按 id 排序；
+/// 保存方式未改变的记录不会被更新
+pub(crate) fn store(
+    conn: &DbConn,
+    raw_list: &[RawHistory],
+    history_list: &[History],
+    storage: HistoryStorage,
+    report: &mut ConvertReport,
+) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::histories::dsl::*;
+
+    report.bytes_before += raw_list.iter().map(stored_size).sum::<usize>();
+
+    // 当前的快照与它被引用的次数
+    let mut snapshot: Option<(u32, &str)> = None;
+    let mut deltas = 0;
+    for (raw, history) in raw_list.iter().zip(history_list) {
+        let stored = match storage {
+            HistoryStorage::Delta => crate::delta::choose(
+                snapshot.map(|(_, snapshot)| snapshot),
+                deltas,
+                history.get_markdown(),
+            )?,
+            HistoryStorage::Full => None,
+        };
+        let (content, base, stored) = match (stored, snapshot) {
+            (Some(stored), Some((snapshot_id, _))) => {
+                deltas += 1;
+                report.deltas += 1;
+                (None, Some(snapshot_id), Some(stored))
+            }
+            _ => {
+                snapshot = Some((history.get_id(), history.get_markdown()));
+                deltas = 0;
+                report.snapshots += 1;
+                (Some(String::from(history.get_markdown())), None, None)
+            }
+        };
+
+        report.bytes_after +=
+            content.as_ref().map_or(0, String::len) + stored.as_ref().map_or(0, Vec::len);
+        if raw.markdown == content && raw.base_id == base && raw.delta == stored {
+            continue;
+        }
+        diesel::update(histories.filter(id.eq(raw.id)))
+            .set((markdown.eq(content), base_id.eq(base), delta.eq(stored)))
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to convert history {}", raw.id), err)
+            })?;
+    }
+
+    report.posts += 1;
+    report.histories += history_list.len();
+    Ok(())
 }
 
 impl AuthInsert for History {
@@ -84,10 +421,43 @@ impl AuthInsert for History {
 
         user.auth()?;
 
+        let mut values = InsertHistory::from(&*self);
+        values.user_id = Some(user.get_id());
+
+        // 尽量保存为相对于文章最新快照的差量
+        let snapshot = histories
+            .filter(post_id.eq(self.post_id))
+            .filter(base_id.is_null())
+            .order(id.desc())
+            .select((id, markdown))
+            .first::<(u32, Option<String>)>(conn)
+            .optional()
+            .map_err(|err| {
+                NoteError::from_diesel(String::from("Failed to query history snapshot"), err)
+            })?;
+        if let Some((snapshot_id, snapshot)) = snapshot {
+            let deltas = histories
+                .filter(base_id.eq(snapshot_id))
+                .count()
+                .get_result::<i64>(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(String::from("Failed to count history deltas"), err)
+                })?;
+            let stored = crate::delta::choose(
+                Some(snapshot.as_deref().unwrap_or("")),
+                deltas as usize,
+                self.get_markdown(),
+            )?;
+            if let Some(stored) = stored {
+                values.markdown = None;
+                values.base_id = Some(snapshot_id);
+                values.delta = Some(stored);
+            }
+        }
         diesel::insert_into(histories)
-            .values(InsertHistory::from(&*self))
+            .values(values)
             .execute(conn)
-            .map_err(|err| NoteError::SQLError(format!("Failed to insert history: {}", err)))?;
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to insert history"), err))?;
 
         Ok(crate::get_last_insert_rowid(conn)?)
     }
@@ -100,22 +470,34 @@ impl AuthDelete for History {
 
         user.auth()?;
 
-        diesel::delete(histories.filter(id.eq(self.get_id())))
-            .execute(conn)
-            .map_err(|err| NoteError::SQLError(format!("Failed to delete history: {}", err)))?;
-
-        Ok(())
-    }
-}
+        conn.transaction::<_, NoteError, _>(|| {
+            // 依赖这条快照的差量先还原为完整内容
+            let dependents = histories
+                .filter(base_id.eq(self.get_id()))
+                .load::<RawHistory>(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(String::from("Failed to query history deltas"), err)
+                })?;
+            for dependent in History::from_raw(conn, dependents)? {
+                diesel::update(histories.filter(id.eq(dependent.get_id())))
+                    .set((
+                        markdown.eq(Some(String::from(dependent.get_markdown()))),
+                        base_id.eq(None::<u32>),
+                        delta.eq(None::<Vec<u8>>),
+                    ))
+                    .execute(conn)
+                    .map_err(|err| {
+                        NoteError::from_diesel(String::from("Failed to expand history"), err)
+                    })?;
+            }
 
-impl From<&RawHistory> for History {
-    fn from(history: &RawHistory) -> History {
-        History {
-            id: history.id,
-            post_id: history.post_id,
-            markdown: history.markdown.clone(),
-            time: history.time,
-        }
+            diesel::delete(histories.filter(id.eq(self.get_id())))
+                .execute(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(String::from("Failed to delete history"), err)
+                })?;
+            Ok(())
+        })
     }
 }
 
@@ -125,6 +507,9 @@ impl From<&History> for InsertHistory {
             post_id: history.get_post_id(),
             time: history.get_time(),
             markdown: Some(String::from(history.get_markdown())),
+            user_id: history.get_user_id(),
+            base_id: None,
+            delta: None,
         }
     }
 }
@@ -0,0 +1,780 @@
+//! 从 Markdown 目录（例如 Obsidian 仓库）导入文章
+//!
+//! 每个 `.md` 文件成为一篇文章，标题取 front matter 中的 `title`，没有时取文件名，`index.md` 取目录名。
+//! 目录对应它的上级文章：同级的 `<目录名>.md` 或目录中的 `index.md`，都没有时新建一篇以目录名为标题的空文章。
+//! 所有文章导入后，指向目录中文件的链接改写为按 id 的 wiki 链接或附件地址，再同步链接边。
+//! 导出时生成的链接文件（front matter 中有 `link`）还原为上级文章指向目标文章的边
+use crate::auth::{AuthInsert, AuthUser};
+use crate::edge::Edge;
+use crate::history::History;
+use crate::link::WikiLink;
+use crate::post::Post;
+use crate::{DbConn, NoteError};
+
+use diesel::Connection;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+/// 目录对应的文章
+const INDEX_FILE: &str = "index.md";
+/// 附件地址中需要转义的字符
+const ATTACHMENT_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// 导入选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportOptions {
+    /// 附件复制到的目录，为空时不复制附件，也不改写对附件的引用
+    pub attachments_dir: Option<PathBuf>,
+    /// 附件目录对外的地址前缀
+    pub attachments_url: String,
+    /// 是否从 git 记录导入历史记录
+    pub git_history: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> ImportOptions {
+        ImportOptions {
+            attachments_dir: None,
+            attachments_url: String::from("/attachments"),
+            git_history: false,
+        }
+    }
+}
+
+/// 导入结果
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// 导入的文章数
+    pub posts: usize,
+    /// 为没有对应文章的目录新建的文章数
+    pub folders: usize,
+    /// 从链接文件还原的边数
+    pub edges: usize,
+    /// 同步的链接边数
+    pub links: usize,
+    /// 复制的附件数
+    pub attachments: usize,
+    /// 从 git 记录导入的历史记录数
+    pub histories: usize,
+    /// 找不到目标的链接，格式为 `<文件>: <链接>`
+    pub unresolved: Vec<String>,
+    /// 与其他文章标题相同的文章，按标题的链接无法区分它们，格式为 `<标题>: <文件>, <文件>`
+    pub ambiguous: Vec<String>,
+}
+
+/// 将目录 `dir` 导入到 `parent_id` 下
+///
+/// 文章在同一个事务中导入，任何一篇失败时都不会留下导入了一半的文章，附件在事务提交后复制
+pub fn import_markdown(
+    conn: &DbConn,
+    user: &AuthUser,
+    parent_id: u32,
+    dir: &Path,
+    options: &ImportOptions,
+) -> Result<ImportReport, NoteError> {
+    user.auth()?;
+    Post::from_id(conn, parent_id)?;
+    let vault = Vault::scan(dir)?;
+
+    let mut report = conn.transaction::<_, NoteError, _>(|| {
+        let mut importer = Importer {
+            conn,
+            user,
+            dir,
+            options,
+            vault: &vault,
+            ids: BTreeMap::new(),
+            stubs: vec![],
+            report: ImportReport::default(),
+        };
+        importer.import(parent_id)?;
+        Ok(importer.report)
+    })?;
+
+    if let Some(attachments_dir) = &options.attachments_dir {
+        for attachment in &vault.attachments {
+            let from = dir.join(attachment);
+            let to = attachments_dir.join(attachment);
+            if let Some(parent) = to.parent() {
+                std::fs::create_dir_all(parent).map_err(|err| NoteError::from_io(parent, err))?;
+            }
+            std::fs::copy(&from, &to).map_err(|err| NoteError::from_io(&from, err))?;
+            report.attachments += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// 目录中的一个 Markdown 文件
+struct Note {
+    markdown: String,
+    title: String,
+    /// 链接文件指向的文件
+    link: Option<PathBuf>,
+}
+
+/// 目录中的文件，路径都相对于导入的目录
+struct Vault {
+    notes: BTreeMap<PathBuf, Note>,
+    attachments: Vec<PathBuf>,
+    /// 含有 Markdown 文件的目录
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl Vault {
+    fn scan(dir: &Path) -> Result<Vault, NoteError> {
+        let mut vault = Vault {
+            notes: BTreeMap::new(),
+            attachments: vec![],
+            dirs: BTreeSet::new(),
+        };
+        vault.scan_dir(dir, Path::new(""))?;
+
+        // 链接文件指向的文件需要在全部扫描后才能确定
+        let mut links = vec![];
+        for (path, note) in &vault.notes {
+            let target = crate::front_matter::parse(&note.markdown)
+                .ok()
+                .and_then(|metadata| {
+                    metadata
+                        .extra
+                        .get("link")
+                        .and_then(|link| link.as_str().map(String::from))
+                })
+                .and_then(|link| normalize(&parent_of(path).join(link)))
+                .filter(|target| target != path && vault.notes.contains_key(target));
+            if let Some(target) = target {
+                links.push((path.clone(), target));
+            }
+        }
+        for (path, target) in links {
+            if let Some(note) = vault.notes.get_mut(&path) {
+                note.link = Some(target);
+            }
+        }
+
+        Ok(vault)
+    }
+
+    /// 扫描 `root` 中的 `dir`，返回其中是否有 Markdown 文件，跳过隐藏的文件与目录
+    ///
+    /// 指向目录的符号链接也被跳过，避免链接回上级目录时无限递归
+    fn scan_dir(&mut self, root: &Path, dir: &Path) -> Result<bool, NoteError> {
+        let full = root.join(dir);
+        let mut entries = std::fs::read_dir(&full)
+            .map_err(|err| NoteError::from_io(&full, err))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .map_err(|err| NoteError::from_io(&full, err))?;
+        entries.sort();
+
+        let mut has_notes = false;
+        for entry in entries {
+            let name = match entry.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = dir.join(&name);
+
+            if entry.is_dir() {
+                let metadata = std::fs::symlink_metadata(&entry)
+                    .map_err(|err| NoteError::from_io(&entry, err))?;
+                if metadata.file_type().is_symlink() {
+                    continue;
+                }
+                if self.scan_dir(root, &path)? {
+                    self.dirs.insert(path);
+                    has_notes = true;
+                }
+            } else if entry.extension().is_some_and(|ext| ext == "md") {
+                let markdown = std::fs::read_to_string(&entry)
+                    .map_err(|err| NoteError::from_io(&entry, err))?;
+                let title = crate::front_matter::parse(&markdown)
+                    .ok()
+                    .and_then(|metadata| {
+                        metadata
+                            .extra
+                            .get("title")
+                            .and_then(|title| title.as_str().map(String::from))
+                    })
+                    .filter(|title| !title.trim().is_empty())
+                    .unwrap_or_else(|| match name.as_str() {
+                        INDEX_FILE => folder_name(root, dir),
+                        _ => name.trim_end_matches(".md").to_string(),
+                    });
+                self.notes.insert(
+                    path,
+                    Note {
+                        markdown,
+                        title,
+                        link: None,
+                    },
+                );
+                has_notes = true;
+            } else {
+                self.attachments.push(path);
+            }
+        }
+
+        Ok(has_notes)
+    }
+
+    /// 可以作为文章导入的文件，不包括链接文件
+    fn post(&self, path: &Path) -> Option<&Note> {
+        self.notes.get(path).filter(|note| note.link.is_none())
+    }
+
+    /// 按 Obsidian 的规则查找 `from` 目录中引用的 `target`：
+    /// 先按相对于 `from` 的路径，再按相对于根目录的路径，最后按文件名，都可以省略 `.md`
+    fn resolve(&self, from: &Path, target: &str) -> Option<PathBuf> {
+        let exists = |path: &Path| {
+            self.post(path).is_some() || self.attachments.iter().any(|file| file == path)
+        };
+        let with_md = format!("{}.md", target);
+        for candidate in &[target, with_md.as_str()] {
+            for base in &[from, Path::new("")] {
+                if let Some(path) = normalize(&base.join(candidate)) {
+                    if exists(&path) {
+                        return Some(path);
+                    }
+                }
+            }
+        }
+
+        let name = Path::new(target)
+            .file_name()?
+            .to_string_lossy()
+            .into_owned();
+        let with_md = format!("{}.md", name);
+        self.notes
+            .keys()
+            .filter(|path| self.post(path).is_some())
+            .chain(self.attachments.iter())
+            .find(|path| {
+                path.file_name()
+                    .is_some_and(|file| file == name.as_str() || file == with_md.as_str())
+            })
+            .cloned()
+    }
+}
+
+struct Importer<'a> {
+    conn: &'a DbConn,
+    user: &'a AuthUser,
+    dir: &'a Path,
+    options: &'a ImportOptions,
+    vault: &'a Vault,
+    /// 已导入的文件及其文章 id
+    ids: BTreeMap<PathBuf, u32>,
+    /// 链接文件所在目录的文章与它指向的文件
+    stubs: Vec<(u32, PathBuf)>,
+    report: ImportReport,
+}
+
+impl Importer<'_> {
+    fn import(&mut self, parent_id: u32) -> Result<(), NoteError> {
+        let root = Path::new("");
+        let root_id = match self.vault.post(Path::new(INDEX_FILE)) {
+            Some(_) => self.import_note(Path::new(INDEX_FILE), parent_id)?,
+            None => parent_id,
+        };
+        self.import_dir(root, root_id)?;
+
+        for (parent, target) in std::mem::take(&mut self.stubs) {
+            if let Some(target_id) = self.ids.get(&target) {
+                Edge::new(parent, *target_id).insert(self.conn, self.user)?;
+                self.report.edges += 1;
+            }
+        }
+
+        // 所有文章导入后才能把指向文件的链接改写为文章 id
+        let vault = self.vault;
+        let ids = self.ids.clone();
+        for (path, post_id) in &ids {
+            let note = &vault.notes[path];
+            let (markdown, unresolved) = self.rewrite(path, &note.markdown);
+            for target in unresolved {
+                self.report
+                    .unresolved
+                    .push(format!("{}: {}", path.display(), target));
+            }
+            if markdown != note.markdown {
+                Post::replace_inserted_markdown(self.conn, *post_id, &markdown)?;
+            }
+            if self.options.git_history {
+                self.seed_history(path, *post_id, &markdown)?;
+            }
+
+            let resolution = crate::link::sync(self.conn, self.user, *post_id, &markdown)?;
+            self.report.links += resolution.resolved.len();
+            for link in resolution.unresolved {
+                let link = match link {
+                    WikiLink::Title(title) => format!("[[{}]]", title),
+                    WikiLink::Id(id) => format!("[[#{}]]", id),
+                };
+                self.report
+                    .unresolved
+                    .push(format!("{}: {}", path.display(), link));
+            }
+        }
+
+        let mut titles: BTreeMap<String, Vec<&Path>> = BTreeMap::new();
+        for path in ids.keys() {
+            let title = &vault.notes[path].title;
+            titles.entry(title.to_lowercase()).or_default().push(path);
+        }
+        for paths in titles.values().filter(|paths| paths.len() > 1) {
+            let files = paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>();
+            self.report.ambiguous.push(format!(
+                "{}: {}",
+                vault.notes[paths[0]].title,
+                files.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// 导入 `dir` 中的文章与子目录，放在 `parent_id` 下
+    fn import_dir(&mut self, dir: &Path, parent_id: u32) -> Result<(), NoteError> {
+        let vault = self.vault;
+        for (path, note) in &vault.notes {
+            if parent_of(path) != dir || self.ids.contains_key(path) {
+                continue;
+            }
+            match &note.link {
+                Some(target) => self.stubs.push((parent_id, target.clone())),
+                None => {
+                    self.import_note(path, parent_id)?;
+                }
+            }
+        }
+
+        for sub in &vault.dirs {
+            if parent_of(sub) != dir {
+                continue;
+            }
+            let mut sibling = sub.clone().into_os_string();
+            sibling.push(".md");
+            let sibling = PathBuf::from(sibling);
+            let index = sub.join(INDEX_FILE);
+
+            let folder_id = if vault.post(&sibling).is_some() {
+                self.ids[&sibling]
+            } else if vault.post(&index).is_some() {
+                self.import_note(&index, parent_id)?
+            } else {
+                let name = sub
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let post = Post::new(None, name, Some(String::new()));
+                self.report.folders += 1;
+                post.insert_under(self.conn, self.user, parent_id)?
+            };
+            self.import_dir(sub, folder_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// 按原文导入 `path`，链接在所有文章导入后改写
+    fn import_note(&mut self, path: &Path, parent_id: u32) -> Result<u32, NoteError> {
+        let note = &self.vault.notes[path];
+        let post = Post::new(None, note.title.clone(), Some(note.markdown.clone()));
+        let post_id =
+            post.insert_under(self.conn, self.user, parent_id)
+                .map_err(|err| match err {
+                    NoteError::Validation(message) => {
+                        NoteError::Validation(format!("{}: {}", path.display(), message))
+                    }
+                    err => err,
+                })?;
+
+        self.ids.insert(path.to_path_buf(), post_id);
+        self.report.posts += 1;
+        Ok(post_id)
+    }
+
+    /// 改写 `path` 中的链接，返回改写后的内容与找不到目标的引用
+    fn rewrite(&self, path: &Path, markdown: &str) -> (String, Vec<String>) {
+        let from = parent_of(path);
+        let lookup = |target: &str| {
+            let found = self.vault.resolve(from, target)?;
+            match self.vault.post(&found) {
+                Some(note) => self
+                    .ids
+                    .get(&found)
+                    .map(|post_id| Target::Note(*post_id, note.title.clone())),
+                None => Some(Target::Attachment(
+                    self.options
+                        .attachments_dir
+                        .as_ref()
+                        .map(|_| self.attachment_url(&found)),
+                )),
+            }
+        };
+        rewrite(markdown, &lookup)
+    }
+
+    fn attachment_url(&self, path: &Path) -> String {
+        let mut url = String::from(self.options.attachments_url.trim_end_matches('/'));
+        for part in path.components() {
+            url.push('/');
+            url.push_str(
+                &utf8_percent_encode(&part.as_os_str().to_string_lossy(), ATTACHMENT_ENCODE_SET)
+                    .to_string(),
+            );
+        }
+        url
+    }
+
+    /// 按 git 记录为 `post_id` 补充更早的历史记录，与当前内容相同的记录会被跳过
+    fn seed_history(&mut self, path: &Path, post_id: u32, current: &str) -> Result<(), NoteError> {
+        let log = self.git(&[
+            "log",
+            "--follow",
+            "--format=%x00%H %at",
+            "--name-only",
+            "--",
+            &path.to_string_lossy(),
+        ])?;
+
+        let mut versions = vec![];
+        for record in log.split('\0').filter(|record| !record.trim().is_empty()) {
+            let mut lines = record.lines().filter(|line| !line.is_empty());
+            let (hash, time) = match lines.next().and_then(|line| line.split_once(' ')) {
+                Some(header) => header,
+                None => continue,
+            };
+            let (time, file) = match (time.parse::<u32>(), lines.next()) {
+                (Ok(time), Some(file)) => (time, file),
+                _ => continue,
+            };
+            // 删除文件的提交中没有内容
+            if let Ok(content) = self.git(&["show", &format!("{}:{}", hash, file)]) {
+                versions.push((time, content));
+            }
+        }
+        versions.reverse();
+
+        let mut last: Option<String> = None;
+        let mut histories = vec![];
+        for (time, content) in versions {
+            let (markdown, _) = self.rewrite(path, &content);
+            if last.as_deref() != Some(markdown.as_str()) {
+                histories.push((time, markdown.clone()));
+            }
+            last = Some(markdown);
+        }
+        // 当前内容已经在插入文章时记录
+        if histories
+            .last()
+            .is_some_and(|(_, markdown)| markdown == current)
+        {
+            histories.pop();
+        }
+
+        for (time, markdown) in histories {
+            History::with_time(post_id, &markdown, time).insert(self.conn, self.user)?;
+            self.report.histories += 1;
+        }
+        Ok(())
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String, NoteError> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.dir)
+            .args(args)
+            .output()
+            .map_err(|err| NoteError::from_io(self.dir, err))?;
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(NoteError::Io {
+                message: format!("Failed to run git in {}: {}", self.dir.display(), message),
+                source: None,
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// 引用的目标
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    /// 导入的文章，值为文章 id 与标题
+    Note(u32, String),
+    /// 附件，值为附件地址，不复制附件时为空
+    Attachment(Option<String>),
+}
+
+/// 改写 `markdown` 中的 `[[链接]]`、`![[嵌入]]` 与相对路径的 Markdown 链接，跳过代码块与行内代码
+///
+/// 指向文章的链接改写为按 id 的 wiki 链接 `[[#id|显示文字]]`，指向附件的链接改写为附件地址。
+/// 返回改写后的内容与找不到目标的 Markdown 链接，找不到目标的 wiki 链接由链接同步报告
+fn rewrite(markdown: &str, lookup: &dyn Fn(&str) -> Option<Target>) -> (String, Vec<String>) {
+    let mut result = String::with_capacity(markdown.len());
+    let mut unresolved = vec![];
+    let mut in_fence = false;
+
+    for line in markdown.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if fence {
+            in_fence = !in_fence;
+        }
+        if fence || in_fence {
+            result.push_str(line);
+            continue;
+        }
+
+        let mut in_code = false;
+        let mut pos = 0;
+        while pos < line.len() {
+            let rest = &line[pos..];
+            if rest.starts_with('`') {
+                in_code = !in_code;
+            } else if !in_code {
+                if let Some((replacement, len)) = rewrite_link(rest, lookup, &mut unresolved) {
+                    result.push_str(&replacement);
+                    pos += len;
+                    continue;
+                }
+            }
+            let len = rest.chars().next().map(char::len_utf8).unwrap_or(1);
+            result.push_str(&rest[..len]);
+            pos += len;
+        }
+    }
+
+    (result, unresolved)
+}
+
+/// 改写 `rest` 开头的链接，返回改写结果与原链接的长度，开头不是链接时返回空
+fn rewrite_link(
+    rest: &str,
+    lookup: &dyn Fn(&str) -> Option<Target>,
+    unresolved: &mut Vec<String>,
+) -> Option<(String, usize)> {
+    let (bang, link) = match rest.strip_prefix('!') {
+        Some(link) => ("!", link),
+        None => ("", rest),
+    };
+
+    if let Some(inner) = link.strip_prefix("[[") {
+        let end = inner.find("]]")?;
+        let inner = &inner[..end];
+        if inner.contains('[') {
+            return None;
+        }
+        let len = bang.len() + end + 4;
+        return Some((
+            rewrite_wiki(bang, inner, lookup).unwrap_or_else(|| rest[..len].to_string()),
+            len,
+        ));
+    }
+
+    let text_end = link.strip_prefix('[')?.find("](")? + 1;
+    let text = &link[1..text_end];
+    if text.contains('[') || text.contains(']') {
+        return None;
+    }
+    let dest_start = text_end + 2;
+    let dest_end = dest_start + link[dest_start..].find(')')?;
+    let len = bang.len() + dest_end + 1;
+    let original = &rest[..len];
+
+    // 目标后可以有用空格隔开的标题
+    let dest = link[dest_start..dest_end].trim();
+    let (target, suffix) = match dest.strip_prefix('<') {
+        Some(dest) => match dest.split_once('>') {
+            Some((target, suffix)) => (target, suffix),
+            None => return Some((original.to_string(), len)),
+        },
+        None => match dest.find(char::is_whitespace) {
+            Some(index) => (&dest[..index], &dest[index..]),
+            None => (dest, ""),
+        },
+    };
+    if target.is_empty()
+        || target.starts_with('#')
+        || target.starts_with('/')
+        || target.contains(':')
+    {
+        return Some((original.to_string(), len));
+    }
+
+    let target = percent_decode_str(target).decode_utf8_lossy();
+    let (path, fragment) = match target.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (target.as_ref(), None),
+    };
+    let replacement = match lookup(path) {
+        Some(Target::Note(post_id, title)) if bang.is_empty() => {
+            let text = match text.is_empty() {
+                true => title.as_str(),
+                false => text,
+            };
+            id_link(post_id, fragment, text)
+        }
+        Some(Target::Attachment(Some(url))) => {
+            let url = match fragment {
+                Some(fragment) => format!("{}#{}", url, fragment),
+                None => url,
+            };
+            format!("{}[{}]({}{})", bang, text, url, suffix)
+        }
+        Some(_) => original.to_string(),
+        None => {
+            unresolved.push(original.to_string());
+            original.to_string()
+        }
+    };
+    Some((replacement, len))
+}
+
+/// 改写 `[[inner]]`，不需要改写时返回空
+fn rewrite_wiki(
+    bang: &str,
+    inner: &str,
+    lookup: &dyn Fn(&str) -> Option<Target>,
+) -> Option<String> {
+    let (target, alias) = match inner.split_once('|') {
+        Some((target, alias)) => (target, Some(alias)),
+        None => (inner, None),
+    };
+    let (target, section) = match target.split_once('#') {
+        Some((target, section)) => (target.trim(), Some(section)),
+        None => (target.trim(), None),
+    };
+    if target.is_empty() {
+        return None;
+    }
+
+    match lookup(target)? {
+        Target::Note(post_id, title) => {
+            let text = match (alias, section) {
+                (Some(alias), _) => String::from(alias),
+                (None, Some(section)) => format!("{}#{}", title, section),
+                (None, None) => title,
+            };
+            Some(id_link(post_id, section, &text))
+        }
+        Target::Attachment(Some(url)) => {
+            // 嵌入图片时 `|` 后是尺寸，替代文字使用文件名
+            let text = match alias {
+                Some(alias) if bang.is_empty() => alias,
+                _ => target,
+            };
+            Some(format!("{}[{}]({})", bang, text, url))
+        }
+        Target::Attachment(None) => None,
+    }
+}
+
+/// 指向文章 `post_id` 的 wiki 链接，显示 `text`
+fn id_link(post_id: u32, section: Option<&str>, text: &str) -> String {
+    match section {
+        Some(section) => format!("[[#{}#{}|{}]]", post_id, section, text),
+        None => format!("[[#{}|{}]]", post_id, text),
+    }
+}
+
+/// `root` 中的目录 `dir` 的名字，`dir` 为空时取 `root` 的名字
+fn folder_name(root: &Path, dir: &Path) -> String {
+    let name = match dir.file_name() {
+        Some(name) => Some(name.to_os_string()),
+        None => root
+            .canonicalize()
+            .ok()
+            .and_then(|root| root.file_name().map(|name| name.to_os_string())),
+    };
+    name.map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| INDEX_FILE.trim_end_matches(".md").to_string())
+}
+
+/// 文件所在的目录
+fn parent_of(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+/// 去掉路径中的 `.` 与 `..`，超出根目录时返回空
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => result.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{folder_name, normalize, rewrite, Target};
+    use std::path::{Path, PathBuf};
+
+    fn lookup(target: &str) -> Option<Target> {
+        match target {
+            "notes/rust" | "rust.md" | "Rust" => Some(Target::Note(3, String::from("Rust"))),
+            "img/a b.png" | "a b.png" => Some(Target::Attachment(Some(String::from(
+                "/attachments/img/a%20b.png",
+            )))),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn links_are_rewritten_to_ids_and_attachments() {
+        let markdown =
+            "[[notes/rust#Setup|setup]] [[Rust]] [[Rust#Intro]] [[Missing]] ![[a b.png|300]]\n\
+                        [guide](rust.md) ![alt](img/a%20b.png \"title\") [web](https://a.b/c.md)\n\
+                        `[[notes/rust]]` [gone](gone.md)\n\
+                        ```\n[[notes/rust]]\n```\n";
+        let (result, unresolved) = rewrite(markdown, &lookup);
+
+        assert_eq!(
+            result,
+            "[[#3#Setup|setup]] [[#3|Rust]] [[#3#Intro|Rust#Intro]] [[Missing]] ![a b.png](/attachments/img/a%20b.png)\n\
+             [[#3|guide]] ![alt](/attachments/img/a%20b.png \"title\") [web](https://a.b/c.md)\n\
+             `[[notes/rust]]` [gone](gone.md)\n\
+             ```\n[[notes/rust]]\n```\n"
+        );
+        assert_eq!(unresolved, vec![String::from("[gone](gone.md)")]);
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(
+            normalize(Path::new("a/./b/../c.md")),
+            Some(PathBuf::from("a/c.md"))
+        );
+        assert_eq!(normalize(Path::new("a/../../c.md")), None);
+        assert_eq!(
+            folder_name(Path::new("/vault"), Path::new("docs/api")),
+            "api"
+        );
+    }
+}
@@ -7,6 +7,9 @@ pub struct InsertHistory {
     pub post_id: u32,
     pub time: u32,
     pub markdown: Option<String>,
+    pub user_id: Option<u32>,
+    pub base_id: Option<u32>,
+    pub delta: Option<Vec<u8>>,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -14,6 +17,7 @@ pub struct InsertHistory {
 pub struct InsertEdge {
     pub from_post: u32,
     pub to_post: u32,
+    pub relation: u8,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -21,6 +25,18 @@ pub struct InsertEdge {
 pub struct InsertPost {
     pub title: String,
     pub markdown: Option<String>,
+    pub slug: Option<String>,
+    /// 以下字段为 `None` 时更新不会修改
+    pub created_at: Option<u32>,
+    pub updated_at: Option<u32>,
+    pub created_by: Option<u32>,
+    pub updated_by: Option<u32>,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[table_name = "tags"]
+pub struct InsertTag {
+    pub name: String,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -28,6 +44,7 @@ pub struct InsertPost {
 pub struct InsertToken {
     pub user_id: u32,
     pub token: String,
+    pub time: u32,
 }
 
 #[derive(Insertable, AsChangeset)]
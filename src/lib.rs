@@ -1,27 +1,62 @@
 #[macro_use]
 extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
 
 pub mod insert;
 pub mod raw;
 pub mod schema;
 
 pub mod auth;
+pub mod backup;
+pub mod blame;
+pub mod config;
+pub mod delta;
+pub mod diff;
 pub mod edge;
+pub mod error;
+pub mod export;
+pub mod feed;
+pub mod front_matter;
+pub mod graph;
 pub mod history;
+pub mod import;
+pub mod link;
+pub mod merge;
+pub mod migration;
 pub mod post;
+pub mod render;
+pub mod retention;
+pub mod service;
+pub mod site;
+pub mod slug;
+pub mod tag;
 pub mod token;
 pub mod user;
 
+#[cfg(test)]
+mod test_db;
+
 extern crate serde;
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 
-use serde::{Deserialize, Serialize};
+pub use config::NotesConfig;
+pub use error::NoteError;
+pub use migration::{check_schema_version, migrate};
+pub use service::Notes;
 
-const TOKEN_LEN: u32 = 32;
+pub type DbConn = diesel::MysqlConnection;
 
-type DbConn = diesel::MysqlConnection;
+/// 连接数据库，并拒绝在未迁移到当前版本的数据库上运行
+pub fn connect(database_url: &str) -> Result<DbConn, NoteError> {
+    use diesel::Connection;
+
+    let conn = DbConn::establish(database_url)?;
+    check_schema_version(&conn)?;
+    Ok(conn)
+}
 
 pub fn get_last_insert_rowid(conn: &DbConn) -> Result<u32, NoteError> {
     use crate::diesel::RunQueryDsl;
@@ -35,26 +70,12 @@ pub fn get_last_insert_rowid(conn: &DbConn) -> Result<u32, NoteError> {
 
     let return_id = diesel::select(last_insert_id)
         .get_result::<i32>(conn)
-        .map_err(|err| NoteError::SQLError(format!("Failed to query insert id: {}", err)))?;
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query insert id"), err))?;
     Ok(return_id.try_into().unwrap_or(0))
 }
 
-// TODO: 进一步展开 SQLError
-/// Note 错误类型
-#[derive(Debug, Serialize, Deserialize)]
-pub enum NoteError {
-    /// 无法找到用户
-    UserNotFound(String),
-    /// 认证失败
-    AuthError(String),
-    /// 没有权限
-    NoPermission(String),
-    /// SQL 错误
-    SQLError(String),
-}
-
 /// 生成一个长度为 `token_len` 的随机字符串，作为 Token
-pub fn gen_token() -> String {
+pub fn gen_token(token_len: u32) -> String {
     use rand::distributions::Alphanumeric;
     use rand::{thread_rng, Rng};
     use std::iter;
@@ -63,7 +84,7 @@ pub fn gen_token() -> String {
     iter::repeat(())
         .map(|()| rng.sample(Alphanumeric))
         .map(char::from)
-        .take(TOKEN_LEN as usize)
+        .take(token_len as usize)
         .collect()
 }
 
@@ -0,0 +1,423 @@
+//! 文中的 wiki 链接，`[[标题]]` 或 `[[#id]]`
+use crate::auth::AuthUser;
+use crate::edge::{Edge, Relation};
+use crate::post::Post;
+use crate::render::RenderExtension;
+use crate::{DbConn, NoteError};
+
+use pulldown_cmark::{CowStr, Event, Tag};
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// 一个 wiki 链接
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WikiLink {
+    /// `[[标题]]`，也支持 `[[标题|显示文字]]` 与 `[[标题#小节]]`
+    Title(String),
+    /// `[[#id]]`，也支持 `[[#id|显示文字]]` 与 `[[#id#小节]]`
+    Id(u32),
+}
+
+/// 链接的解析结果
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LinkResolution {
+    /// 找到的文章 id
+    pub resolved: Vec<u32>,
+    /// 找不到对应文章的链接
+    pub unresolved: Vec<WikiLink>,
+}
+
+/// 引用了某篇文章的文章
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backlink {
+    pub post_id: u32,
+    pub title: String,
+    /// 指向该文章的边的种类，仅在文中引用而没有边时为空
+    pub relations: Vec<Relation>,
+    /// 文中引用处的上下文
+    pub snippets: Vec<String>,
+}
+
+/// 截取引用处上下文时，前后各保留的字符数
+const SNIPPET_RADIUS: usize = 60;
+
+/// 将 wiki 链接渲染为 HTML 链接的渲染扩展，找不到地址的链接保留原文
+#[derive(Default)]
+pub struct LinkRenderer {
+    /// 小写的标题对应的地址
+    titles: HashMap<String, String>,
+    ids: HashMap<u32, String>,
+}
+
+impl LinkRenderer {
+    pub fn new() -> LinkRenderer {
+        LinkRenderer::default()
+    }
+
+    /// 设置文章的地址，标题相同时先加入的优先
+    pub fn add(&mut self, post_id: u32, title: &str, url: String) {
+        self.titles
+            .entry(title.to_lowercase())
+            .or_insert_with(|| url.clone());
+        self.ids.insert(post_id, url);
+    }
+
+    fn url(&self, link: &WikiLink) -> Option<&String> {
+        match link {
+            WikiLink::Title(title) => self.titles.get(&title.to_lowercase()),
+            WikiLink::Id(id) => self.ids.get(id),
+        }
+    }
+
+    fn push_text<'a>(&self, text: &mut String, events: &mut Vec<Event<'a>>) {
+        if text.is_empty() {
+            return;
+        }
+
+        let mut pos = 0;
+        for (link, range) in find_links(text) {
+            let url = match self.url(&link) {
+                Some(url) => url,
+                None => continue,
+            };
+            // `[[标题#小节|显示文字]]`
+            let inner = &text[range.start + 2..range.end - 2];
+            let (target, label) = match inner.split_once('|') {
+                Some((target, label)) => (target, label.trim()),
+                None => (inner, inner.trim()),
+            };
+            let target = match link {
+                WikiLink::Id(_) => target.trim_start().strip_prefix('#').unwrap_or(target),
+                WikiLink::Title(_) => target,
+            };
+            let href = match target.split_once('#') {
+                Some((_, section)) => format!("{}#{}", url, crate::render::anchor_id(section)),
+                None => url.clone(),
+            };
+
+            if pos < range.start {
+                events.push(Event::Text(CowStr::from(
+                    text[pos..range.start].to_string(),
+                )));
+            }
+            events.push(Event::Html(CowStr::from(format!(
+                "<a href=\"{}\">{}</a>",
+                crate::render::escape_html(&href),
+                crate::render::escape_html(label)
+            ))));
+            pos = range.end;
+        }
+        if pos < text.len() {
+            events.push(Event::Text(CowStr::from(text[pos..].to_string())));
+        }
+        text.clear();
+    }
+}
+
+impl RenderExtension for LinkRenderer {
+    fn process<'a>(&self, events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+        let mut result = Vec::with_capacity(events.len());
+        // 解析器会在 `[` 处拆分文本，相邻的文本合并后再查找链接
+        let mut text = String::new();
+        let mut in_code_block = false;
+
+        for event in events {
+            match event {
+                Event::Text(content) if !in_code_block => text.push_str(&content),
+                event => {
+                    self.push_text(&mut text, &mut result);
+                    match &event {
+                        Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                        Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+                        _ => {}
+                    }
+                    result.push(event);
+                }
+            }
+        }
+        self.push_text(&mut text, &mut result);
+
+        result
+    }
+}
+
+/// 找出文中所有链接及其位置，跳过代码块、行内代码与 `![[...]]` 嵌入
+pub fn find_links(markdown: &str) -> Vec<(WikiLink, Range<usize>)> {
+    let mut links = vec![];
+    let mut in_fence = false;
+    let mut offset = 0;
+
+    for line in markdown.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        let mut in_code = false;
+        let mut pos = 0;
+        while pos < line.len() {
+            let rest = &line[pos..];
+            if rest.starts_with('`') {
+                in_code = !in_code;
+                pos += 1;
+                continue;
+            }
+            if !in_code && rest.starts_with("[[") && !line[..pos].ends_with('!') {
+                if let Some(end) = rest.find("]]") {
+                    let inner = &rest[2..end];
+                    if !inner.contains('[') {
+                        if let Some(link) = parse_link(inner) {
+                            let start = line_start + pos;
+                            links.push((link, start..start + end + 2));
+                        }
+                        pos += end + 2;
+                        continue;
+                    }
+                }
+            }
+            pos += rest.chars().next().map(char::len_utf8).unwrap_or(1);
+        }
+    }
+
+    links
+}
+
+/// 找出文中所有不重复的链接
+pub fn parse_links(markdown: &str) -> Vec<WikiLink> {
+    let mut links = vec![];
+    for (link, _) in find_links(markdown) {
+        if !links.contains(&link) {
+            links.push(link);
+        }
+    }
+    links
+}
+
+fn parse_link(inner: &str) -> Option<WikiLink> {
+    let target = inner.split('|').next()?.trim();
+    if let Some(id) = target.strip_prefix('#') {
+        if let Ok(id) = id.split('#').next()?.trim().parse::<u32>() {
+            return Some(WikiLink::Id(id));
+        }
+    }
+
+    let title = target.split('#').next()?.trim();
+    match title.is_empty() {
+        true => None,
+        false => Some(WikiLink::Title(String::from(title))),
+    }
+}
+
+/// 截取 `range` 前后各 `radius` 个字符作为上下文，空白折叠为一个空格
+pub fn snippet(markdown: &str, range: Range<usize>, radius: usize) -> String {
+    let start = markdown[..range.start]
+        .char_indices()
+        .rev()
+        .nth(radius.saturating_sub(1))
+        .map(|(index, _)| index)
+        .unwrap_or(0);
+    let end = markdown[range.end..]
+        .char_indices()
+        .nth(radius)
+        .map(|(index, _)| range.end + index)
+        .unwrap_or_else(|| markdown.len());
+
+    let mut result = String::new();
+    if start > 0 {
+        result.push('…');
+    }
+    result.push_str(
+        &markdown[start..end]
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" "),
+    );
+    if end < markdown.len() {
+        result.push('…');
+    }
+    result
+}
+
+/// 链接是否指向 `post`
+fn links_to(link: &WikiLink, post: &Post) -> bool {
+    match link {
+        WikiLink::Id(id) => *id == post.get_id(),
+        WikiLink::Title(title) => title.to_lowercase() == post.get_title().to_lowercase(),
+    }
+}
+
+/// 找出所有通过边或文中链接引用 `post` 的文章
+pub fn backlinks(conn: &DbConn, post: &Post) -> Result<Vec<Backlink>, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::posts::dsl::*;
+
+    let mut sources = Vec::<(u32, Vec<Relation>)>::new();
+    for relation in &[Relation::Child, Relation::Link] {
+        for edge in Edge::get_from_list_of(conn, post.get_id(), *relation)? {
+            match sources
+                .iter_mut()
+                .find(|(from, _)| *from == edge.get_from())
+            {
+                Some((_, relations)) => relations.push(*relation),
+                None => sources.push((edge.get_from(), vec![*relation])),
+            }
+        }
+    }
+
+    // 还没有同步成边的文中引用
+    let mentions = posts
+        .select(id)
+        .filter(deleted_at.is_null())
+        .filter(
+            markdown
+                .like(format!("%[[#{}]]%", post.get_id()))
+                .or(markdown.like(format!("%[[{}%", escape_like(post.get_title())))),
+        )
+        .load::<u32>(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(
+                format!("Failed to query mentions of {}", post.get_id()),
+                err,
+            )
+        })?;
+    for mention in mentions {
+        if !sources.iter().any(|(from, _)| *from == mention) {
+            sources.push((mention, vec![]));
+        }
+    }
+
+    let mut result = vec![];
+    for (source_id, relations) in sources {
+        if source_id == post.get_id() {
+            continue;
+        }
+        let source = Post::from_id(conn, source_id)?;
+        let snippets = find_links(source.get_markdown())
+            .into_iter()
+            .filter(|(link, _)| links_to(link, post))
+            .map(|(_, range)| snippet(source.get_markdown(), range, SNIPPET_RADIUS))
+            .collect::<Vec<String>>();
+        if relations.is_empty() && snippets.is_empty() {
+            continue;
+        }
+
+        result.push(Backlink {
+            post_id: source_id,
+            title: String::from(source.get_title()),
+            relations,
+            snippets,
+        });
+    }
+
+    Ok(result)
+}
+
+/// 转义 LIKE 中的通配符
+fn escape_like(content: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    for c in content.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// 解析 `markdown` 中的链接，忽略指向 `post_id` 自身的链接
+pub fn resolve(conn: &DbConn, post_id: u32, markdown: &str) -> Result<LinkResolution, NoteError> {
+    let mut resolution = LinkResolution::default();
+
+    for link in parse_links(markdown) {
+        let post = match &link {
+            WikiLink::Title(title) => Post::from_title(conn, title),
+            WikiLink::Id(id) => Post::from_id(conn, *id),
+        };
+        match post {
+            Ok(post) => {
+                if post.get_id() != post_id && !resolution.resolved.contains(&post.get_id()) {
+                    resolution.resolved.push(post.get_id());
+                }
+            }
+            Err(NoteError::NotFound { .. }) => resolution.unresolved.push(link),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(resolution)
+}
+
+/// 按 `markdown` 中的链接同步 `post_id` 的链接边
+pub fn sync(
+    conn: &DbConn,
+    user: &AuthUser,
+    post_id: u32,
+    markdown: &str,
+) -> Result<LinkResolution, NoteError> {
+    let resolution = resolve(conn, post_id, markdown)?;
+    Edge::update_to_ids(conn, user, post_id, &resolution.resolved, Relation::Link)?;
+    Ok(resolution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_links, parse_links, snippet, LinkRenderer, WikiLink};
+    use crate::render::Renderer;
+
+    #[test]
+    fn links_are_parsed_outside_code() {
+        let markdown = "See [[Rust]] and [[#12]], [[Rust|again]] or [[Diesel#Setup]].\n\
+                        `[[Inline]]` ![[image.png]]\n\
+                        ```\n[[Fenced]]\n```\n\
+                        [[ 中文 标题 ]] [[]] [[#abc]] [[#7#Setup|seven]]";
+
+        assert_eq!(
+            parse_links(markdown),
+            vec![
+                WikiLink::Title(String::from("Rust")),
+                WikiLink::Id(12),
+                WikiLink::Title(String::from("Diesel")),
+                WikiLink::Title(String::from("中文 标题")),
+                WikiLink::Id(7),
+            ]
+        );
+    }
+
+    #[test]
+    fn snippet_keeps_context_around_link() {
+        let markdown = "第一行\n\nSome   text before [[Target]] and after.";
+        let (_, range) = find_links(markdown).remove(0);
+
+        assert_eq!(
+            snippet(markdown, range.clone(), 100),
+            "第一行 Some text before [[Target]] and after."
+        );
+        assert_eq!(snippet(markdown, range, 5), "…fore [[Target]] and…");
+    }
+
+    #[test]
+    fn links_are_rendered_with_known_urls() {
+        let mut links = LinkRenderer::new();
+        links.add(3, "Rust", String::from("rust.html"));
+        let mut renderer = Renderer::new();
+        renderer.add_extension(Box::new(links));
+
+        let html =
+            renderer.render("[[rust#Getting Started|guide]], [[#3]], [[#3#Setup|setup]] and [[Missing]]\n\n`[[Rust]]`");
+        assert!(html.contains(
+            "<a href=\"rust.html#getting-started\" rel=\"noopener noreferrer\">guide</a>"
+        ));
+        assert!(html.contains("<a href=\"rust.html\" rel=\"noopener noreferrer\">#3</a>"));
+        assert!(html.contains("<a href=\"rust.html#setup\" rel=\"noopener noreferrer\">setup</a>"));
+        assert!(html.contains("[[Missing]]"));
+        assert!(html.contains("<code>[[Rust]]</code>"));
+    }
+}
@@ -0,0 +1,223 @@
+//! 按行的三方合并
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+
+/// 合并结果中的一段
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeChunk {
+    /// 没有冲突的内容
+    Resolved(String),
+    /// 双方修改了同一处
+    Conflict {
+        /// 冲突处在共同祖先中的起始行，从 1 开始
+        start_line: usize,
+        base: String,
+        ours: String,
+        theirs: String,
+    },
+}
+
+/// 三方合并的结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeResult {
+    pub chunks: Vec<MergeChunk>,
+}
+
+impl MergeResult {
+    /// 是否没有冲突
+    pub fn is_clean(&self) -> bool {
+        self.conflicts().is_empty()
+    }
+    /// 所有冲突
+    pub fn conflicts(&self) -> Vec<&MergeChunk> {
+        self.chunks
+            .iter()
+            .filter(|chunk| matches!(chunk, MergeChunk::Conflict { .. }))
+            .collect()
+    }
+    /// 没有冲突时的合并结果
+    pub fn merged(&self) -> Option<String> {
+        match self.is_clean() {
+            true => Some(self.with_markers()),
+            false => None,
+        }
+    }
+    /// 合并结果，冲突处使用 git 风格的冲突标记
+    pub fn with_markers(&self) -> String {
+        let mut result = String::new();
+        for chunk in &self.chunks {
+            match chunk {
+                MergeChunk::Resolved(content) => result.push_str(content),
+                MergeChunk::Conflict {
+                    base, ours, theirs, ..
+                } => {
+                    push_section(&mut result, "<<<<<<< ours", ours);
+                    push_section(&mut result, "||||||| base", base);
+                    push_section(&mut result, "=======", theirs);
+                    result.push_str(">>>>>>> theirs\n");
+                }
+            }
+        }
+        result
+    }
+}
+
+fn push_section(result: &mut String, marker: &str, content: &str) {
+    result.push_str(marker);
+    result.push('\n');
+    result.push_str(content);
+    if !content.is_empty() && !content.ends_with('\n') {
+        result.push('\n');
+    }
+}
+
+/// 一方对共同祖先的一处修改，将 `start..end` 行替换为 `lines`
+struct Change<'a> {
+    ours: bool,
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+fn changes<'a>(base: &[&str], other: &[&'a str], ours: bool) -> Vec<Change<'a>> {
+    let mut changes: Vec<Change> = vec![];
+    let mut in_change = false;
+    for op in capture_diff_slices(Algorithm::Myers, base, other) {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            in_change = false;
+            continue;
+        }
+
+        match changes.last_mut() {
+            Some(last) if in_change => {
+                last.end = old_range.end;
+                last.lines.extend_from_slice(&other[new_range]);
+            }
+            _ => changes.push(Change {
+                ours,
+                start: old_range.start,
+                end: old_range.end,
+                lines: other[new_range].to_vec(),
+            }),
+        }
+        in_change = true;
+    }
+    changes
+}
+
+/// 将 `changes` 应用到共同祖先的 `start..end` 行
+fn apply(base: &[&str], start: usize, end: usize, changes: &[&Change]) -> String {
+    let mut result = String::new();
+    let mut pos = start;
+    for change in changes {
+        result.push_str(&base[pos..change.start].concat());
+        result.push_str(&change.lines.concat());
+        pos = change.end;
+    }
+    result.push_str(&base[pos..end].concat());
+    result
+}
+
+fn push_resolved(chunks: &mut Vec<MergeChunk>, content: String) {
+    if content.is_empty() {
+        return;
+    }
+    match chunks.last_mut() {
+        Some(MergeChunk::Resolved(last)) => last.push_str(&content),
+        _ => chunks.push(MergeChunk::Resolved(content)),
+    }
+}
+
+/// 以 `base` 为共同祖先合并 `ours` 与 `theirs`
+///
+/// 只有一方修改的地方自动合并，双方做了相同修改的地方也视为没有冲突，
+/// 修改了同一处或相邻行时视为冲突
+pub fn merge(base: &str, ours: &str, theirs: &str) -> MergeResult {
+    let base_lines = base.split_inclusive('\n').collect::<Vec<&str>>();
+    let ours_lines = ours.split_inclusive('\n').collect::<Vec<&str>>();
+    let theirs_lines = theirs.split_inclusive('\n').collect::<Vec<&str>>();
+
+    let mut all = changes(&base_lines, &ours_lines, true);
+    all.extend(changes(&base_lines, &theirs_lines, false));
+    all.sort_by_key(|change| (change.start, change.end));
+
+    let mut chunks = vec![];
+    let mut pos = 0;
+    let mut index = 0;
+    while index < all.len() {
+        let start = all[index].start;
+        let mut end = all[index].end;
+        let mut next = index + 1;
+        while next < all.len() && all[next].start <= end {
+            end = end.max(all[next].end);
+            next += 1;
+        }
+
+        push_resolved(&mut chunks, base_lines[pos..start].concat());
+        let group = &all[index..next];
+        let ours_changes = group
+            .iter()
+            .filter(|change| change.ours)
+            .collect::<Vec<&Change>>();
+        let theirs_changes = group
+            .iter()
+            .filter(|change| !change.ours)
+            .collect::<Vec<&Change>>();
+        let ours_content = apply(&base_lines, start, end, &ours_changes);
+        let theirs_content = apply(&base_lines, start, end, &theirs_changes);
+
+        if theirs_changes.is_empty() || ours_content == theirs_content {
+            push_resolved(&mut chunks, ours_content);
+        } else if ours_changes.is_empty() {
+            push_resolved(&mut chunks, theirs_content);
+        } else {
+            chunks.push(MergeChunk::Conflict {
+                start_line: start + 1,
+                base: base_lines[start..end].concat(),
+                ours: ours_content,
+                theirs: theirs_content,
+            });
+        }
+
+        pos = end;
+        index = next;
+    }
+    push_resolved(&mut chunks, base_lines[pos..].concat());
+
+    MergeResult { chunks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, MergeChunk};
+
+    #[test]
+    fn non_overlapping_edits_are_merged() {
+        let base = "a\nb\nc\nd\ne\n";
+        let result = merge(base, "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\nf\n");
+        assert_eq!(result.merged().as_deref(), Some("A\nb\nc\nd\nE\nf\n"));
+
+        let result = merge(base, "a\nb\nX\nd\ne\n", "a\nb\nX\nd\ne\n");
+        assert_eq!(result.merged().as_deref(), Some("a\nb\nX\nd\ne\n"));
+    }
+
+    #[test]
+    fn overlapping_edits_conflict() {
+        let result = merge("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc");
+        assert_eq!(result.conflicts().len(), 1);
+        assert_eq!(
+            result.chunks[1],
+            MergeChunk::Conflict {
+                start_line: 2,
+                base: String::from("b\nc\n"),
+                ours: String::from("ours\nc\n"),
+                theirs: String::from("theirs\nc"),
+            }
+        );
+        assert_eq!(
+            result.with_markers(),
+            "a\n<<<<<<< ours\nours\nc\n||||||| base\nb\nc\n=======\ntheirs\nc\n>>>>>>> theirs\n"
+        );
+        assert!(result.merged().is_none());
+    }
+}
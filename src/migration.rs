@@ -0,0 +1,95 @@
+//! 内嵌的数据库迁移
+use crate::raw::RawPost;
+use crate::{DbConn, NoteError};
+
+embed_migrations!();
+
+/// 当前代码所需的数据库版本，即最后一个迁移的版本号
+pub const SCHEMA_VERSION: &str = "20261019163521";
+
+/// Index 文章的 id，`Post::insert` 默认将新文章挂在它下面
+pub const INDEX_POST_ID: u32 = 1;
+
+/// 执行所有未执行的迁移，确保 Index 文章存在，并为旧文章生成 slug、标签与元数据
+pub fn migrate(conn: &DbConn) -> Result<(), NoteError> {
+    embedded_migrations::run(conn).map_err(NoteError::from)?;
+    seed(conn)?;
+    crate::slug::backfill(conn)?;
+    crate::tag::backfill(conn)?;
+    crate::front_matter::backfill(conn)
+}
+
+/// 检查数据库是否已经迁移到当前代码所需的版本
+pub fn check_schema_version(conn: &DbConn) -> Result<(), NoteError> {
+    use diesel_migrations::MigrationConnection;
+
+    let versions = conn.previously_run_migration_versions().map_err(|err| {
+        NoteError::from_diesel(String::from("Failed to query schema version"), err)
+    })?;
+
+    match versions.contains(SCHEMA_VERSION) {
+        true => Ok(()),
+        false => Err(NoteError::Unavailable {
+            message: format!(
+                "Database schema is outdated (latest applied: {}, required: {}), run migrations first",
+                versions.iter().max().map(String::as_str).unwrap_or("none"),
+                SCHEMA_VERSION
+            ),
+            source: None,
+        }),
+    }
+}
+
+/// 插入 Index 文章（若不存在）
+fn seed(conn: &DbConn) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::posts::dsl::*;
+
+    let count = posts
+        .filter(id.eq(INDEX_POST_ID))
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query index post"), err))?;
+    if count > 0 {
+        return Ok(());
+    }
+
+    diesel::insert_into(posts)
+        .values(RawPost {
+            id: INDEX_POST_ID,
+            title: String::from("Index"),
+            markdown: Some(String::from("`Hello, World!`")),
+            slug: None,
+            created_at: chrono::Utc::now().timestamp() as u32,
+            updated_at: chrono::Utc::now().timestamp() as u32,
+            created_by: None,
+            updated_by: None,
+            revision: 1,
+            deleted_at: None,
+        })
+        .execute(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to insert index post"), err))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn schema_version_is_latest_migration() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+        let latest = std::fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let version = name.split('_').next()?.replace('-', "");
+                Some(version)
+            })
+            .max()
+            .unwrap();
+
+        assert_eq!(latest, super::SCHEMA_VERSION);
+    }
+}
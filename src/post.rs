@@ -1,18 +1,50 @@
 //! 文章
 use crate::auth::{AuthDelete, AuthInsert, AuthUpdate, AuthUser};
-use crate::edge::Edge;
+use crate::blame::{BlameLine, Revision};
+use crate::edge::{Edge, Relation};
+use crate::front_matter::Metadata;
 use crate::history::History;
 use crate::insert::InsertPost;
+use crate::link::{Backlink, LinkResolution};
+use crate::merge::MergeResult;
 use crate::raw::RawPost;
+use crate::tag::Tag;
 use crate::{DbConn, NoteError};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+/// 文章列表的筛选条件
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PostFilter {
+    /// 需要同时带有的标签
+    pub include_tags: Vec<String>,
+    /// 不能带有的标签
+    pub exclude_tags: Vec<String>,
+    /// 需要同时满足的元数据，`(字段名, 值)`，字段名见 `front_matter::INDEXED_FIELDS`
+    pub metadata: Vec<(String, String)>,
+    pub offset: i64,
+    pub limit: Option<i64>,
+}
 
 #[derive(Serialize)]
 pub struct Post {
     id: u32,
     title: String,
     markdown: Option<String>,
+    slug: Option<String>,
+    /// 创建时间
+    created_at: u32,
+    /// 最后修改时间
+    updated_at: u32,
+    /// 创建者，早于记录作者的文章为空
+    created_by: Option<u32>,
+    /// 最后修改者，早于记录作者的文章为空
+    updated_by: Option<u32>,
+    /// 版本号，每次修改加一，修改时需要与数据库中的一致
+    revision: u32,
+    /// 移入回收站的时间，不在回收站中时为空
+    deleted_at: Option<u32>,
 }
 
 impl Post {
@@ -28,12 +60,67 @@ impl Post {
             None => "",
         }
     }
+    /// 文章的 slug，尚未插入的文章没有 slug
+    pub fn get_slug(&self) -> Option<&str> {
+        self.slug.as_deref()
+    }
+    pub fn get_created_at(&self) -> u32 {
+        self.created_at
+    }
+    pub fn get_updated_at(&self) -> u32 {
+        self.updated_at
+    }
+    pub fn get_created_by(&self) -> Option<u32> {
+        self.created_by
+    }
+    pub fn get_updated_by(&self) -> Option<u32> {
+        self.updated_by
+    }
+    pub fn get_deleted_at(&self) -> Option<u32> {
+        self.deleted_at
+    }
+    pub fn get_revision(&self) -> u32 {
+        self.revision
+    }
+    /// 设置修改所基于的版本号，`update` 时与数据库中的版本号比较
+    pub fn set_revision(&mut self, revision: u32) {
+        self.revision = revision;
+    }
+    /// 由 id 与版本号生成的 HTTP ETag
+    pub fn etag(&self) -> String {
+        format!("\"{}-{}\"", self.id, self.revision)
+    }
+
+    /// 解析文章开头的 front matter
+    pub fn metadata(&self) -> Result<Metadata, NoteError> {
+        crate::front_matter::parse(self.get_markdown())
+    }
+    /// 将文章的 front matter 改为 `metadata`，需要再调用 `update` 保存
+    pub fn set_metadata(&mut self, metadata: &Metadata) -> Result<(), NoteError> {
+        self.markdown = Some(crate::front_matter::with_metadata(
+            self.get_markdown(),
+            metadata,
+        )?);
+        Ok(())
+    }
+
+    /// 渲染为过滤过的 HTML
+    pub fn render_html(&self) -> String {
+        crate::render::render_html(self.get_markdown())
+    }
 
     pub fn new(id: Option<u32>, title: String, markdown: Option<String>) -> Post {
         Post {
             id: id.unwrap_or_else(|| 0),
             title,
             markdown,
+            slug: None,
+            created_at: 0,
+            updated_at: 0,
+            created_by: None,
+            updated_by: None,
+            revision: 0,
+            deleted_at: None,
         }
     }
     pub fn from_id(conn: &DbConn, post_id: u32) -> Result<Post, NoteError> {
@@ -43,79 +130,488 @@ impl Post {
         Ok(Post::from(
             &posts
                 .filter(id.eq(post_id))
+                .filter(deleted_at.is_null())
                 .first::<RawPost>(conn)
-                .map_err(|err| {
-                    NoteError::SQLError(format!("Failed to query post from id{}:{}", post_id, err))
-                })?,
+                .map_err(|err| NoteError::from_query("post", post_id, err))?,
+        ))
+    }
+    /// 获取回收站中的文章
+    pub fn from_trash(conn: &DbConn, post_id: u32) -> Result<Post, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::posts::dsl::*;
+
+        Ok(Post::from(
+            &posts
+                .filter(id.eq(post_id))
+                .filter(deleted_at.is_not_null())
+                .first::<RawPost>(conn)
+                .map_err(|err| NoteError::from_query("trashed post", post_id, err))?,
         ))
     }
+    /// 获取回收站中的所有文章，最近删除的在前
+    pub fn list_trash(conn: &DbConn) -> Result<Vec<Post>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::posts::dsl::*;
+
+        Ok(posts
+            .filter(deleted_at.is_not_null())
+            .order((deleted_at.desc(), id.asc()))
+            .load::<RawPost>(conn)
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to query trash"), err))?
+            .iter()
+            .map(Post::from)
+            .collect::<Vec<Post>>())
+    }
+
+    /// 通过 slug 获取文章，文章改名前的 slug 同样有效
+    pub fn from_slug(conn: &DbConn, post_slug: &str) -> Result<Post, NoteError> {
+        match crate::slug::owner_of(conn, post_slug)? {
+            Some(post_id) => Post::from_id(conn, post_id),
+            None => Err(NoteError::not_found("post", post_slug)),
+        }
+    }
+
+    /// 通过别名获取文章，有多篇文章使用同一别名时返回 id 最小的一篇
+    pub fn from_alias(conn: &DbConn, alias: &str) -> Result<Post, NoteError> {
+        match crate::front_matter::find_posts(conn, "alias", alias)?.first() {
+            Some(post_id) => Post::from_id(conn, *post_id),
+            None => Err(NoteError::not_found("post", alias)),
+        }
+    }
+
+    /// 通过标题获取文章，有多篇同名文章时返回 id 最小的一篇
+    pub fn from_title(conn: &DbConn, post_title: &str) -> Result<Post, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::posts::dsl::*;
+
+        Ok(Post::from(
+            &posts
+                .filter(title.eq(post_title))
+                .filter(deleted_at.is_null())
+                .order(id.asc())
+                .first::<RawPost>(conn)
+                .map_err(|err| NoteError::from_query("post", post_title, err))?,
+        ))
+    }
+
+    /// 按 id 顺序列出符合 `filter` 的文章
+    pub fn list(conn: &DbConn, filter: &PostFilter) -> Result<Vec<Post>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::posts::dsl::*;
+        use crate::schema::{post_metadata, post_tags, tags};
+
+        let mut query = posts.filter(deleted_at.is_null()).into_boxed();
+        for (field, field_value) in &filter.metadata {
+            query = query.filter(
+                id.eq_any(
+                    post_metadata::table
+                        .filter(post_metadata::name.eq(field))
+                        .filter(post_metadata::value.eq(field_value))
+                        .select(post_metadata::post_id),
+                ),
+            );
+        }
+        for tag_name in &filter.include_tags {
+            query = query.filter(
+                id.eq_any(
+                    post_tags::table
+                        .inner_join(tags::table)
+                        .filter(tags::name.eq(crate::tag::normalize(tag_name)))
+                        .select(post_tags::post_id),
+                ),
+            );
+        }
+        for tag_name in &filter.exclude_tags {
+            query = query.filter(
+                id.ne_all(
+                    post_tags::table
+                        .inner_join(tags::table)
+                        .filter(tags::name.eq(crate::tag::normalize(tag_name)))
+                        .select(post_tags::post_id),
+                ),
+            );
+        }
+
+        Ok(query
+            .order(id.asc())
+            .offset(filter.offset)
+            .limit(filter.limit.unwrap_or(i64::MAX))
+            .load::<RawPost>(conn)
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to query posts"), err))?
+            .iter()
+            .map(Post::from)
+            .collect::<Vec<Post>>())
+    }
+
+    /// 获取文章的标签
+    pub fn tags(&self, conn: &DbConn) -> Result<Vec<Tag>, NoteError> {
+        Tag::get_post_tags(conn, self.id)
+    }
+    /// 按文中的标签同步文章的标签，返回当前的标签名
+    pub fn sync_tags(&self, conn: &DbConn, user: &AuthUser) -> Result<Vec<String>, NoteError> {
+        crate::tag::sync(conn, user, self.id, self.get_markdown())
+    }
+
+    /// 解析文中的 wiki 链接
+    pub fn resolve_links(&self, conn: &DbConn) -> Result<LinkResolution, NoteError> {
+        crate::link::resolve(conn, self.id, self.get_markdown())
+    }
+    /// 按文中的 wiki 链接同步链接边，返回解析结果
+    pub fn sync_links(&self, conn: &DbConn, user: &AuthUser) -> Result<LinkResolution, NoteError> {
+        crate::link::sync(conn, user, self.id, self.get_markdown())
+    }
+
+    /// 获取所有引用了当前文章的文章，以及引用处的上下文
+    pub fn backlinks(&self, conn: &DbConn) -> Result<Vec<Backlink>, NoteError> {
+        crate::link::backlinks(conn, self)
+    }
+
+    /// 插入当前文章，并挂在 `parent_id` 下，所有步骤在一个事务中完成
+    pub fn insert_under(
+        &self,
+        conn: &DbConn,
+        user: &AuthUser,
+        parent_id: u32,
+    ) -> Result<u32, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::posts;
+
+        user.auth()?;
+
+        conn.transaction::<_, NoteError, _>(|| {
+            let mut values = InsertPost::from(&*self);
+            let now = chrono::Utc::now().timestamp() as u32;
+            values.created_at = Some(now);
+            values.updated_at = Some(now);
+            values.created_by = Some(user.get_id());
+            values.updated_by = Some(user.get_id());
+            diesel::insert_into(posts::table)
+                .values(values)
+                .execute(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(String::from("Failed to insert post"), err)
+                })?;
+
+            let insert_id = crate::get_last_insert_rowid(conn)?;
+            let new_slug =
+                crate::slug::unique_slug(conn, &crate::slug::slugify(&self.title), insert_id)?;
+            crate::slug::rename(conn, insert_id, "", &new_slug)?;
+            Edge::new(parent_id, insert_id).insert(conn, user)?;
+            let history = History::new(insert_id, &self.get_markdown());
+            history.insert(&*conn, &*user)?;
+            crate::link::sync(conn, user, insert_id, self.get_markdown())?;
+            crate::tag::sync(conn, user, insert_id, self.get_markdown())?;
+            crate::front_matter::sync(conn, insert_id, self.get_markdown())?;
+            Ok(insert_id)
+        })
+    }
+
+    /// 替换刚插入的文章的内容，同时改写插入时的历史记录，不增加历史记录与版本号，仅用于导入
+    pub(crate) fn replace_inserted_markdown(
+        conn: &DbConn,
+        post_id: u32,
+        new_markdown: &str,
+    ) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::{histories, posts};
+
+        diesel::update(posts::table.filter(posts::id.eq(post_id)))
+            .set(posts::markdown.eq(new_markdown))
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to update post {}", post_id), err)
+            })?;
+        // 插入时只有一条历史记录，它是完整的快照
+        diesel::update(histories::table.filter(histories::post_id.eq(post_id)))
+            .set(histories::markdown.eq(new_markdown))
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to update history of {}", post_id), err)
+            })?;
+        Ok(())
+    }
 }
 
 impl AuthUpdate for Post {
+    /// 修改文章，`revision` 与数据库中的不一致时返回带有当前版本号与差异的 `NoteError::Conflict`
     fn update(&self, conn: &DbConn, user: &AuthUser) -> Result<(), NoteError> {
         use crate::diesel::*;
         use crate::schema::posts::dsl::*;
 
         user.auth()?;
 
-        let history = History::new(self.id, &self.get_markdown());
-        history.insert(&*conn, &*user)?;
+        conn.transaction::<_, NoteError, _>(|| {
+            let origin = Post::from_id(conn, self.id)?;
+            if origin.revision != self.revision {
+                return Err(self.stale(&origin));
+            }
 
-        diesel::update(posts.filter(id.eq(self.id)))
-            .set(InsertPost::from(&*self))
+            let history = History::new(self.id, &self.get_markdown());
+            let mut values = InsertPost::from(&*self);
+            values.updated_at = Some(history.get_time());
+            values.updated_by = Some(user.get_id());
+            let updated = diesel::update(
+                posts
+                    .filter(id.eq(self.id))
+                    .filter(revision.eq(self.revision)),
+            )
+            .set((values, revision.eq(self.revision + 1)))
             .execute(conn)
             .map_err(|err| {
-                NoteError::SQLError(format!("Failed update post {}: {}", self.id, err))
+                NoteError::from_diesel(format!("Failed update post {}", self.id), err)
             })?;
+            if updated == 0 {
+                return Err(self.stale(&Post::from_id(conn, self.id)?));
+            }
+            history.insert(&*conn, &*user)?;
 
-        Ok(())
+            if origin.title != self.title || origin.slug.is_none() {
+                let new_slug =
+                    crate::slug::unique_slug(conn, &crate::slug::slugify(&self.title), self.id)?;
+                let old_slug = origin.get_slug().unwrap_or("");
+                crate::slug::rename(conn, self.id, old_slug, &new_slug)?;
+            }
+            self.sync_links(conn, user)?;
+            self.sync_tags(conn, user)?;
+            crate::front_matter::sync(conn, self.id, self.get_markdown())?;
+
+            Ok(())
+        })
+    }
+}
+
+impl Post {
+    /// 以历史记录 `base_history_id` 为共同祖先，三方合并 `theirs` 与 `ours`
+    ///
+    /// 通常 `base_history_id` 为开始编辑时的版本，`theirs` 为当前内容，`ours` 为自己的修改
+    pub fn merge(
+        &self,
+        conn: &DbConn,
+        base_history_id: u32,
+        theirs: &str,
+        ours: &str,
+    ) -> Result<MergeResult, NoteError> {
+        let base = History::from_id(conn, base_history_id)?;
+        if base.get_post_id() != self.id {
+            return Err(NoteError::Validation(format!(
+                "History {} does not belong to post {}",
+                base_history_id, self.id
+            )));
+        }
+        Ok(crate::merge::merge(base.get_markdown(), ours, theirs))
+    }
+
+    /// 将当前内容的每一行归属到最后修改它的历史记录
+    pub fn blame(&self, conn: &DbConn) -> Result<Vec<BlameLine>, NoteError> {
+        let history_list = History::get_history(self.id, conn)?;
+        let versions = history_list
+            .iter()
+            .map(|history| {
+                let revision = Revision {
+                    history_id: history.get_id(),
+                    time: history.get_time(),
+                    user_id: history.get_user_id(),
+                };
+                (revision, history.get_markdown())
+            })
+            .collect::<Vec<(Revision, &str)>>();
+        Ok(crate::blame::blame(&versions, self.get_markdown()))
+    }
+
+    /// 基于过期版本修改时的错误
+    fn stale(&self, current: &Post) -> NoteError {
+        NoteError::Conflict {
+            message: format!(
+                "Post {} has been modified (revision {}, based on {})",
+                self.id, current.revision, self.revision
+            ),
+            current_revision: Some(current.revision),
+            diff: Some(crate::diff::unified(
+                current.get_markdown(),
+                self.get_markdown(),
+                "current",
+                "yours",
+            )),
+            source: None,
+        }
     }
 }
 
 impl AuthInsert for Post {
     fn insert(&self, conn: &DbConn, user: &AuthUser) -> Result<u32, NoteError> {
+        self.insert_under(conn, user, crate::migration::INDEX_POST_ID)
+    }
+}
+
+impl AuthDelete for Post {
+    /// 将文章移入回收站，没有上级的下级文章改挂到 Index，见 `trash`
+    fn delete(&self, conn: &DbConn, user: &AuthUser) -> Result<(), NoteError> {
+        self.trash(conn, user, crate::migration::INDEX_POST_ID)
+    }
+}
+
+impl Post {
+    /// 将文章连同它的边移入回收站，历史记录保留
+    ///
+    /// Index 文章与根文章 `root_id` 不能删除。下级文章改挂到这篇文章的上级文章下（没有上级时挂到 `root_id`），
+    /// 以免整棵子树无法从根文章到达；恢复时原来的边一并恢复，改挂的边被移除
+    pub fn trash(&self, conn: &DbConn, user: &AuthUser, root_id: u32) -> Result<(), NoteError> {
         use crate::diesel::*;
-        use crate::schema::posts;
+        use crate::schema::posts::dsl::*;
 
         user.auth()?;
+        if self.id == crate::migration::INDEX_POST_ID || self.id == root_id {
+            return Err(NoteError::Validation(format!(
+                "The root post {} cannot be deleted",
+                self.id
+            )));
+        }
 
-        diesel::insert_into(posts::table)
-            .values(InsertPost::from(&*self))
-            .execute(conn)
-            .map_err(|err| NoteError::SQLError(format!("Failed to insert post: {}", err)))?;
+        let now = chrono::Utc::now().timestamp() as u32;
+        conn.transaction::<_, NoteError, _>(|| {
+            let mut parents = Edge::get_from_list(conn, self.id)?
+                .iter()
+                .map(Edge::get_from)
+                .filter(|parent| *parent != self.id)
+                .collect::<Vec<u32>>();
+            if parents.is_empty() {
+                parents.push(root_id);
+            }
+            for edge in Edge::get_to_list(conn, self.id)? {
+                let child = edge.get_to();
+                let existing = Edge::get_from_list(conn, child)?;
+                for parent in &parents {
+                    if *parent != child && !existing.iter().any(|edge| edge.get_from() == *parent) {
+                        Edge::new(*parent, child).insert_reattached(conn, user, self.id)?;
+                    }
+                }
+            }
 
-        let insert_id = crate::get_last_insert_rowid(conn)?;
-        Edge::new(1, insert_id).insert(conn, user)?;
-        let history = History::new(insert_id, &self.get_markdown());
-        history.insert(&*conn, &*user)?;
-        Ok(insert_id)
+            diesel::update(posts.filter(id.eq(self.id)).filter(deleted_at.is_null()))
+                .set(deleted_at.eq(Some(now)))
+                .execute(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(format!("Failed to trash post {}", self.id), err)
+                })?;
+            Edge::trash_of(conn, user, self.id, now)
+        })
     }
-}
 
-impl AuthDelete for Post {
-    fn delete(&self, conn: &DbConn, user: &AuthUser) -> Result<(), NoteError> {
+    /// 从回收站中恢复文章
+    ///
+    /// 另一端仍在回收站中的边保持删除，删除时改挂的边在原来的边恢复后移除，文中的链接与标签重新同步
+    pub fn restore(&self, conn: &DbConn, user: &AuthUser) -> Result<(), NoteError> {
         use crate::diesel::*;
         use crate::schema::posts::dsl::*;
 
         user.auth()?;
 
-        // Delete all Edge
-        Edge::update_to_list(conn, user, self.get_id(), vec![])?;
-        Edge::update_from_list(conn, user, self.get_id(), vec![])?;
+        conn.transaction::<_, NoteError, _>(|| {
+            diesel::update(posts.filter(id.eq(self.id)))
+                .set(deleted_at.eq(None::<u32>))
+                .execute(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(format!("Failed to restore post {}", self.id), err)
+                })?;
+
+            for edge in Edge::get_trashed_of(conn, self.id)? {
+                let other = match edge.get_from() == self.id {
+                    true => edge.get_to(),
+                    false => edge.get_from(),
+                };
+                match Post::from_id(conn, other) {
+                    Ok(_) => (),
+                    // 另一端仍在回收站中，随另一端一起恢复
+                    Err(NoteError::NotFound { .. }) => continue,
+                    Err(err) => return Err(err),
+                }
 
-        // Delete all history
-        let history_list = History::get_history(self.get_id(), &*conn)?;
-        for history in history_list {
-            history.delete(&*conn, user)?;
+                // 出链由下面的同步重新生成
+                let outgoing_link =
+                    edge.get_from() == self.id && edge.get_relation() == Relation::Link;
+                let duplicated = Edge::get_to_list_of(conn, edge.get_from(), edge.get_relation())?
+                    .iter()
+                    .any(|active| active.get_to() == edge.get_to());
+                match outgoing_link || duplicated {
+                    true => edge.delete(conn, user)?,
+                    false => edge.restore(conn, user)?,
+                }
+            }
+
+            Edge::remove_reattached(conn, user, self.id)?;
+
+            let post = Post::from_id(conn, self.id)?;
+            post.sync_links(conn, user)?;
+            post.sync_tags(conn, user)?;
+            Ok(())
+        })
+    }
+
+    /// 彻底删除文章、它的边与历史记录，需要管理员权限
+    pub fn purge(&self, conn: &DbConn, user: &AuthUser) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::posts::dsl::*;
+
+        if !user.is_admin() {
+            return Err(NoteError::NoPermission(String::from(
+                "Only admin can purge posts",
+            )));
         }
 
-        diesel::delete(posts.filter(id.eq(self.id)))
-            .execute(conn)
-            .map_err(|err| {
-                NoteError::SQLError(format!("Failed update post {}: {}", self.id, err))
-            })?;
+        conn.transaction::<_, NoteError, _>(|| {
+            Edge::keep_reattached(conn, user, self.id)?;
+            Edge::purge_of(conn, user, self.id)?;
 
-        Ok(())
+            // 从新到旧删除，差量先于它所基于的快照被删除
+            let history_list = History::get_history(self.id, &*conn)?;
+            for history in history_list.into_iter().rev() {
+                history.delete(&*conn, user)?;
+            }
+
+            crate::slug::remove_redirects(conn, self.id)?;
+            crate::tag::remove_post(conn, self.id)?;
+            crate::front_matter::remove_post(conn, self.id)?;
+
+            diesel::delete(posts.filter(id.eq(self.id)))
+                .execute(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(format!("Failed to purge post {}", self.id), err)
+                })?;
+            Ok(())
+        })
+    }
+
+    /// 彻底删除回收站中超过 `retention_days` 天的文章，0 为永久保留，返回被删除的文章 id
+    pub fn purge_trash(
+        conn: &DbConn,
+        user: &AuthUser,
+        retention_days: u32,
+    ) -> Result<Vec<u32>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::posts::dsl::*;
+
+        if !user.is_admin() {
+            return Err(NoteError::NoPermission(String::from(
+                "Only admin can purge posts",
+            )));
+        }
+        if retention_days == 0 {
+            return Ok(vec![]);
+        }
+
+        let now = chrono::Utc::now().timestamp() as u32;
+        let before = now.saturating_sub(retention_days.saturating_mul(24 * 60 * 60));
+        let expired = posts
+            .filter(deleted_at.le(before))
+            .load::<RawPost>(conn)
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to query trash"), err))?;
+
+        let mut purged = vec![];
+        for post in expired.iter().map(Post::from) {
+            post.purge(conn, user)?;
+            purged.push(post.id);
+        }
+        Ok(purged)
     }
 }
 
@@ -124,6 +620,11 @@ impl From<&Post> for InsertPost {
         InsertPost {
             title: String::from(post.get_title()),
             markdown: Some(String::from(post.get_markdown())),
+            slug: post.slug.clone(),
+            created_at: None,
+            updated_at: None,
+            created_by: None,
+            updated_by: None,
         }
     }
 }
@@ -134,6 +635,116 @@ impl From<&RawPost> for Post {
             id: post.id,
             title: post.title.clone(),
             markdown: post.markdown.clone(),
+            slug: post.slug.clone(),
+            created_at: post.created_at,
+            updated_at: post.updated_at,
+            created_by: post.created_by,
+            updated_by: post.updated_by,
+            revision: post.revision,
+            deleted_at: post.deleted_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Post;
+    use crate::auth::{AuthInsert, AuthUpdate};
+    use crate::edge::Edge;
+    use crate::history::History;
+    use crate::migration::INDEX_POST_ID;
+    use crate::test_db;
+    use crate::{DbConn, NoteError};
+
+    fn parents(conn: &DbConn, post_id: u32) -> Vec<u32> {
+        let mut parents = Edge::get_from_list(conn, post_id)
+            .unwrap()
+            .iter()
+            .map(Edge::get_from)
+            .collect::<Vec<u32>>();
+        parents.sort_unstable();
+        parents
+    }
+
+    #[test]
+    #[ignore = "needs a MySQL database in NOTES_TEST_DATABASE_URL"]
+    fn trash_then_restore_drops_reattached_edges() {
+        let conn = test_db::connect();
+        let user = test_db::admin(&conn);
+
+        let parent = Post::new(None, String::from("Parent"), Some(String::from("parent")))
+            .insert(&conn, &user)
+            .unwrap();
+        let child = Post::new(None, String::from("Child"), Some(String::from("child")))
+            .insert_under(&conn, &user, parent)
+            .unwrap();
+
+        match Post::from_id(&conn, INDEX_POST_ID)
+            .unwrap()
+            .trash(&conn, &user, INDEX_POST_ID)
+        {
+            Err(NoteError::Validation(_)) => (),
+            result => panic!("Index post was trashed: {:?}", result),
+        }
+
+        // 下级文章改挂到被删除文章的上级
+        Post::from_id(&conn, parent)
+            .unwrap()
+            .trash(&conn, &user, INDEX_POST_ID)
+            .unwrap();
+        assert_eq!(parents(&conn, child), vec![INDEX_POST_ID]);
+
+        // 恢复后只剩原来的边
+        Post::from_trash(&conn, parent)
+            .unwrap()
+            .restore(&conn, &user)
+            .unwrap();
+        assert_eq!(parents(&conn, parent), vec![INDEX_POST_ID]);
+        assert_eq!(parents(&conn, child), vec![parent]);
+    }
+
+    #[test]
+    #[ignore = "needs a MySQL database in NOTES_TEST_DATABASE_URL"]
+    fn purge_removes_delta_histories() {
+        use crate::diesel::*;
+        use crate::schema::histories;
+
+        let conn = test_db::connect();
+        let user = test_db::admin(&conn);
+
+        let mut markdown = (0..50)
+            .map(|line| format!("line {}\n", line))
+            .collect::<String>();
+        let post_id = Post::new(None, String::from("Log"), Some(markdown.clone()))
+            .insert(&conn, &user)
+            .unwrap();
+        let child = Post::new(None, String::from("Entry"), Some(String::from("entry")))
+            .insert_under(&conn, &user, post_id)
+            .unwrap();
+        for revision in 0..3 {
+            markdown.push_str(&format!("edit {}\n", revision));
+            let mut post = Post::new(Some(post_id), String::from("Log"), Some(markdown.clone()));
+            post.set_revision(Post::from_id(&conn, post_id).unwrap().get_revision());
+            post.update(&conn, &user).unwrap();
+        }
+        let deltas = histories::table
+            .filter(histories::post_id.eq(post_id))
+            .filter(histories::base_id.is_not_null())
+            .count()
+            .get_result::<i64>(&conn)
+            .unwrap();
+        assert!(deltas > 0);
+
+        let post = Post::from_id(&conn, post_id).unwrap();
+        post.trash(&conn, &user, INDEX_POST_ID).unwrap();
+        post.purge(&conn, &user).unwrap();
+
+        assert!(History::get_history(post_id, &conn).unwrap().is_empty());
+        match Post::from_trash(&conn, post_id) {
+            Err(NoteError::NotFound { .. }) => (),
+            result => panic!("Purged post was found: {:?}", result.map(|post| post.id)),
         }
+        // 改挂的边在彻底删除后保留
+        assert_eq!(parents(&conn, child), vec![INDEX_POST_ID]);
     }
 }
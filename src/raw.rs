@@ -1,40 +1,88 @@
 //! 用于读取数据库
 use crate::schema::*;
 
-#[derive(Queryable, Insertable)]
+#[derive(Clone, Queryable, Insertable, Serialize, Deserialize)]
 #[table_name = "histories"]
 pub struct RawHistory {
     pub id: u32,
     pub post_id: u32,
     pub time: u32,
     pub markdown: Option<String>,
+    pub user_id: Option<u32>,
+    /// 差量所基于的快照，为空时 `markdown` 为完整内容
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<Vec<u8>>,
 }
 
-#[derive(Queryable, Insertable)]
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
 #[table_name = "post_edge"]
 pub struct RawEdge {
     pub id: u32,
     pub from_post: u32,
     pub to_post: u32,
+    pub relation: u8,
+    pub deleted_at: Option<u32>,
+    /// 删除文章时为它的下级文章补上的边，值为被删除的文章
+    #[serde(default)]
+    pub reattached_from: Option<u32>,
 }
 
-#[derive(Queryable, Insertable)]
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
 #[table_name = "posts"]
 pub struct RawPost {
     pub id: u32,
     pub title: String,
     pub markdown: Option<String>,
+    pub slug: Option<String>,
+    pub created_at: u32,
+    pub updated_at: u32,
+    pub created_by: Option<u32>,
+    pub updated_by: Option<u32>,
+    pub revision: u32,
+    pub deleted_at: Option<u32>,
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name = "post_metadata"]
+pub struct RawPostMetadata {
+    pub post_id: u32,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "post_slug_redirects"]
+pub struct RawSlugRedirect {
+    pub slug: String,
+    pub post_id: u32,
 }
 
 #[derive(Queryable, Insertable)]
+#[table_name = "post_tags"]
+pub struct RawPostTag {
+    pub post_id: u32,
+    pub tag_id: u32,
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name = "tags"]
+pub struct RawTag {
+    pub id: u32,
+    pub name: String,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
 #[table_name = "tokens"]
 pub struct RawToken {
     pub id: u32,
     pub user_id: u32,
     pub token: String,
+    pub time: u32,
 }
 
-#[derive(Queryable, Insertable)]
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
 #[table_name = "users"]
 pub struct RawUser {
     pub id: u32,
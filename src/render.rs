@@ -0,0 +1,202 @@
+//! Markdown 渲染
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+use std::collections::HashMap;
+
+/// 渲染扩展，在生成 HTML 前改写事件流
+///
+/// 扩展生成的 HTML 同样会经过过滤，不能用于绕过 XSS 过滤
+pub trait RenderExtension {
+    fn process<'a>(&self, events: Vec<Event<'a>>) -> Vec<Event<'a>>;
+}
+
+/// 将 Markdown 渲染为过滤过的 HTML
+///
+/// 支持 CommonMark 与 GFM 的表格、任务列表、删除线以及脚注，标题会带上锚点
+pub struct Renderer {
+    extensions: Vec<Box<dyn RenderExtension>>,
+    sanitizer: ammonia::Builder<'static>,
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        let mut sanitizer = ammonia::Builder::default();
+        sanitizer
+            .add_tags(&["input"])
+            .add_tag_attributes("input", &["type", "checked", "disabled"])
+            .add_tag_attributes("sup", &["class"])
+            .add_tag_attributes("div", &["class", "id"])
+            .add_tag_attribute_values("input", "type", &["checkbox"]);
+        for heading in &["h1", "h2", "h3", "h4", "h5", "h6"] {
+            sanitizer.add_tag_attributes(heading, &["id"]);
+        }
+
+        Renderer {
+            extensions: vec![],
+            sanitizer,
+        }
+    }
+
+    /// 增加一个扩展，扩展按加入顺序执行
+    pub fn add_extension(&mut self, extension: Box<dyn RenderExtension>) {
+        self.extensions.push(extension);
+    }
+
+    /// 渲染 Markdown，开头的 front matter 不会被渲染
+    pub fn render(&self, markdown: &str) -> String {
+        let markdown = crate::front_matter::body(markdown);
+
+        let mut options = Options::empty();
+        options.insert(Options::ENABLE_TABLES);
+        options.insert(Options::ENABLE_FOOTNOTES);
+        options.insert(Options::ENABLE_STRIKETHROUGH);
+        options.insert(Options::ENABLE_TASKLISTS);
+
+        let mut events = Parser::new_ext(markdown, options).collect::<Vec<Event>>();
+        for extension in &self.extensions {
+            events = extension.process(events);
+        }
+        let events = heading_anchors(events);
+
+        let mut unsafe_html = String::new();
+        html::push_html(&mut unsafe_html, events.into_iter());
+        self.sanitizer.clean(&unsafe_html).to_string()
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Renderer {
+        Renderer::new()
+    }
+}
+
+/// 使用默认设置渲染 Markdown
+pub fn render_html(markdown: &str) -> String {
+    Renderer::default().render(markdown)
+}
+
+/// 提取 Markdown 中的纯文本，用于搜索索引，不包括 front matter 与 HTML
+pub fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new(crate::front_matter::body(markdown)) {
+        match event {
+            Event::Text(content) | Event::Code(content) => text.push_str(&content),
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::End(Tag::Paragraph)
+            | Event::End(Tag::Heading(_))
+            | Event::End(Tag::Item)
+            | Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::TableCell) => text.push(' '),
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// 转义 HTML 中的特殊字符
+pub fn escape_html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// 生成标题的锚点，保留各语言的字母与数字，其余字符替换为 `-`
+pub fn anchor_id(text: &str) -> String {
+    let mut anchor = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() || c == '_' {
+            anchor.push(c);
+        } else if !anchor.ends_with('-') {
+            anchor.push('-');
+        }
+    }
+    String::from(anchor.trim_matches('-'))
+}
+
+/// 为标题加上唯一的 id
+fn heading_anchors(events: Vec<Event>) -> Vec<Event> {
+    let mut result = Vec::with_capacity(events.len());
+    let mut used = HashMap::<String, usize>::new();
+    let mut heading: Option<(u32, Vec<Event>)> = None;
+
+    for event in events {
+        match (event, &mut heading) {
+            (Event::Start(Tag::Heading(level)), None) => heading = Some((level, vec![])),
+            (Event::End(Tag::Heading(_)), Some(_)) => {
+                let (level, inner) = heading.take().unwrap();
+                let text = inner
+                    .iter()
+                    .filter_map(|event| match event {
+                        Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                        _ => None,
+                    })
+                    .collect::<String>();
+
+                let mut anchor = anchor_id(&text);
+                if anchor.is_empty() {
+                    anchor = String::from("section");
+                }
+                let count = used.entry(anchor.clone()).or_insert(0);
+                *count += 1;
+                if *count > 1 {
+                    anchor = format!("{}-{}", anchor, *count - 1);
+                }
+
+                result.push(Event::Html(CowStr::from(format!(
+                    "<h{} id=\"{}\">",
+                    level, anchor
+                ))));
+                result.extend(inner);
+                result.push(Event::Html(CowStr::from(format!("</h{}>\n", level))));
+            }
+            (event, Some((_, inner))) => inner.push(event),
+            (event, None) => result.push(event),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_html, plain_text, render_html};
+
+    #[test]
+    fn headings_get_unique_anchors() {
+        let html = render_html("# Hello World\n\n## Hello World\n\n## 你好 世界");
+        assert!(html.contains("<h1 id=\"hello-world\">Hello World</h1>"));
+        assert!(html.contains("<h2 id=\"hello-world-1\">Hello World</h2>"));
+        assert!(html.contains("<h2 id=\"你好-世界\">"));
+    }
+
+    #[test]
+    fn html_is_sanitized() {
+        let html =
+            render_html("<script>alert(1)</script>\n\n- [x] done\n\n[a](javascript:alert(1))");
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("type=\"checkbox\""));
+    }
+
+    #[test]
+    fn plain_text_drops_markup() {
+        assert_eq!(
+            plain_text("---\ntags: [a]\n---\n# Title\n\nSome *bold* `code`\nnext"),
+            "Title Some bold code next"
+        );
+        assert_eq!(
+            escape_html("<a href=\"x\">&'"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;"
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! 历史记录的保留策略
+//!
+//! 最近 `keep_days` 天内的历史记录全部保留；更早的依次分为按小时、按天与按周精简的三段，
+//! 每个小时、天或周中只保留最后一条。每篇文章的第一条与最新一条历史记录总是保留
+use crate::auth::AuthUser;
+use crate::config::NotesConfig;
+use crate::history::{ConvertReport, History, HistoryStorage};
+use crate::{DbConn, NoteError};
+
+use std::collections::HashSet;
+
+const HOUR: u32 = 60 * 60;
+const DAY: u32 = 24 * HOUR;
+const WEEK: u32 = 7 * DAY;
+/// 1970-01-01 是周四，加上该偏移后每周从周一开始
+const WEEK_OFFSET: u32 = 3 * DAY;
+
+/// 历史记录的保留策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// 全部保留的天数，0 为永久保留全部
+    pub keep_days: u32,
+    /// 之后每小时保留一条的天数
+    pub hourly_days: u32,
+    /// 再之后每天保留一条的天数，更早的每周保留一条
+    pub daily_days: u32,
+}
+
+/// 被精简的历史记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrunedHistory {
+    pub id: u32,
+    pub post_id: u32,
+    pub time: u32,
+}
+
+/// 精简历史记录的结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PruneReport {
+    /// 为真时只列出将被删除的历史记录，不修改数据库
+    pub dry_run: bool,
+    pub posts: usize,
+    pub kept: usize,
+    pub pruned: Vec<PrunedHistory>,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(config: &NotesConfig) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_days: config.history_retention_days,
+            hourly_days: config.history_hourly_days,
+            daily_days: config.history_daily_days,
+        }
+    }
+
+    /// `time` 时的历史记录所在的时间段：段的种类与段内的序号，`None` 表示全部保留
+    fn bucket(&self, now: u32, time: u32) -> Option<(u8, u32)> {
+        let age = now.saturating_sub(time);
+        let keep = self.keep_days.saturating_mul(DAY);
+        let hourly = keep.saturating_add(self.hourly_days.saturating_mul(DAY));
+        let daily = hourly.saturating_add(self.daily_days.saturating_mul(DAY));
+
+        if self.keep_days == 0 || age < keep {
+            None
+        } else if age < hourly {
+            Some((0, time / HOUR))
+        } else if age < daily {
+            Some((1, time / DAY))
+        } else {
+            Some((2, time.saturating_add(WEEK_OFFSET) / WEEK))
+        }
+    }
+
+    /// 同一篇文章中需要删除的历史记录
+    ///
+    /// `revisions` 为 `(id, time)`，按时间从旧到新排列
+    pub fn plan(&self, now: u32, revisions: &[(u32, u32)]) -> Vec<u32> {
+        let mut pruned = vec![];
+        for (index, window) in revisions.windows(2).enumerate() {
+            let ((history_id, time), (_, next_time)) = (window[0], window[1]);
+            if index == 0 {
+                continue;
+            }
+            // 同一时间段中只保留最后一条
+            let bucket = self.bucket(now, time);
+            if bucket.is_some() && bucket == self.bucket(now, next_time) {
+                pruned.push(history_id);
+            }
+        }
+        pruned
+    }
+}
+
+/// 按 `policy` 精简所有文章的历史记录，需要管理员权限
+///
+/// 每篇文章在一个事务中完成；被删除的快照所对应的差量会重新压缩
+pub fn prune(
+    conn: &DbConn,
+    user: &AuthUser,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> Result<PruneReport, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::histories::dsl::*;
+
+    if !user.is_admin() {
+        return Err(NoteError::NoPermission(String::from(
+            "Only admin can prune histories",
+        )));
+    }
+
+    let mut report = PruneReport {
+        dry_run,
+        ..PruneReport::default()
+    };
+    if policy.keep_days == 0 {
+        return Ok(report);
+    }
+
+    let now = chrono::Utc::now().timestamp() as u32;
+    let revisions = histories
+        .select((post_id, id, time))
+        .order((post_id.asc(), time.asc(), id.asc()))
+        .load::<(u32, u32, u32)>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query histories"), err))?;
+
+    let mut start = 0;
+    while start < revisions.len() {
+        let current = revisions[start].0;
+        let end = revisions[start..]
+            .iter()
+            .position(|(post, _, _)| *post != current)
+            .map_or(revisions.len(), |offset| start + offset);
+        let post_revisions = revisions[start..end]
+            .iter()
+            .map(|(_, history_id, history_time)| (*history_id, *history_time))
+            .collect::<Vec<(u32, u32)>>();
+        start = end;
+
+        let pruned = policy
+            .plan(now, &post_revisions)
+            .into_iter()
+            .collect::<HashSet<u32>>();
+        report.posts += 1;
+        report.kept += post_revisions.len() - pruned.len();
+        for (history_id, history_time) in &post_revisions {
+            if pruned.contains(history_id) {
+                report.pruned.push(PrunedHistory {
+                    id: *history_id,
+                    post_id: current,
+                    time: *history_time,
+                });
+            }
+        }
+        if dry_run || pruned.is_empty() {
+            continue;
+        }
+
+        conn.transaction::<_, NoteError, _>(|| {
+            let raw_list = crate::history::load_raw(conn, current)?;
+            // 有差量依赖被删除的快照时，先还原内容，删除后重新保存
+            let rebase = raw_list.iter().any(|history| {
+                !pruned.contains(&history.id)
+                    && history.base_id.is_some_and(|base| pruned.contains(&base))
+            });
+            let history_list = match rebase {
+                true => History::from_raw(conn, raw_list.clone())?,
+                false => vec![],
+            };
+
+            diesel::delete(
+                histories.filter(id.eq_any(pruned.iter().copied().collect::<Vec<u32>>())),
+            )
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to prune history of {}", current), err)
+            })?;
+
+            if rebase {
+                let (kept_raw, kept): (Vec<_>, Vec<_>) = raw_list
+                    .into_iter()
+                    .zip(history_list)
+                    .filter(|(history, _)| !pruned.contains(&history.id))
+                    .unzip();
+                crate::history::store(
+                    conn,
+                    &kept_raw,
+                    &kept,
+                    HistoryStorage::Delta,
+                    &mut ConvertReport::default(),
+                )?;
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prune, RetentionPolicy, DAY, HOUR};
+    use crate::auth::AuthInsert;
+    use crate::history::{History, HistoryStorage};
+    use crate::post::Post;
+    use crate::test_db;
+
+    #[test]
+    fn revisions_are_thinned_by_age() {
+        let policy = RetentionPolicy {
+            keep_days: 1,
+            hourly_days: 1,
+            daily_days: 7,
+        };
+        let now = 100 * DAY;
+        let revisions = vec![
+            // 第一条总是保留
+            (1, 8 * DAY),
+            // 按周精简：同一周中只保留最后一条
+            (2, 9 * DAY),
+            (3, 10 * DAY),
+            // 按天精简
+            (4, 95 * DAY + HOUR),
+            (5, 95 * DAY + 2 * HOUR),
+            // 按小时精简
+            (6, 98 * DAY + 10),
+            (7, 98 * DAY + 20),
+            (8, 98 * DAY + HOUR),
+            // 全部保留
+            (9, 99 * DAY + 10),
+            (10, 99 * DAY + 20),
+        ];
+        assert_eq!(policy.plan(now, &revisions), vec![2, 4, 6]);
+
+        // 最新一条总是保留
+        assert!(policy.plan(now, &revisions[3..5]).is_empty());
+
+        let keep_all = RetentionPolicy {
+            keep_days: 0,
+            ..policy
+        };
+        assert!(keep_all.plan(now, &revisions).is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs a MySQL database in NOTES_TEST_DATABASE_URL"]
+    fn pruned_snapshots_are_rebased() {
+        use crate::diesel::*;
+        use crate::schema::histories::dsl::*;
+
+        let conn = test_db::connect();
+        let user = test_db::admin(&conn);
+        let policy = RetentionPolicy {
+            keep_days: 1,
+            hourly_days: 1,
+            daily_days: 7,
+        };
+
+        let base = (0..50)
+            .map(|line| format!("line {}\n", line))
+            .collect::<String>();
+        let revision = |index: u32| format!("{}edit {}\n", base, index);
+        let post = Post::new(None, String::from("Log"), Some(revision(0)))
+            .insert(&conn, &user)
+            .unwrap();
+        let snapshot = History::get_history(post, &conn).unwrap()[0].get_id();
+
+        // 按时间排列为 oldest、snapshot、newer、latest，snapshot 与 newer 在同一个小时内
+        let now = chrono::Utc::now().timestamp() as u32;
+        let hour = (now - 36 * HOUR) / HOUR * HOUR + 10;
+        diesel::update(histories.filter(id.eq(snapshot)))
+            .set(time.eq(hour))
+            .execute(&conn)
+            .unwrap();
+        for (index, history_time) in [(1, now - 40 * DAY), (2, hour + 60), (3, now)] {
+            History::with_time(post, &revision(index), history_time)
+                .insert(&conn, &user)
+                .unwrap();
+        }
+        // 按 id 重新压缩，之后的历史记录都是基于 snapshot 的差量
+        crate::history::convert(&conn, HistoryStorage::Delta).unwrap();
+        assert!(crate::history::load_raw(&conn, post)
+            .unwrap()
+            .iter()
+            .any(|history| history.base_id == Some(snapshot)));
+
+        let report = prune(&conn, &user, &policy, true).unwrap();
+        assert_eq!(
+            report
+                .pruned
+                .iter()
+                .map(|history| history.id)
+                .collect::<Vec<u32>>(),
+            vec![snapshot]
+        );
+        assert_eq!(History::get_history(post, &conn).unwrap().len(), 4);
+
+        prune(&conn, &user, &policy, false).unwrap();
+        let kept = History::get_history(post, &conn)
+            .unwrap()
+            .iter()
+            .map(|history| String::from(history.get_markdown()))
+            .collect::<Vec<String>>();
+        assert_eq!(kept, vec![revision(1), revision(2), revision(3)]);
+        assert!(crate::history::load_raw(&conn, post)
+            .unwrap()
+            .iter()
+            .all(|history| history.base_id != Some(snapshot)));
+    }
+}
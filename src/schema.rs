@@ -4,6 +4,9 @@ table! {
         post_id -> Unsigned<Integer>,
         time -> Unsigned<Integer>,
         markdown -> Nullable<Text>,
+        user_id -> Nullable<Unsigned<Integer>>,
+        base_id -> Nullable<Unsigned<Integer>>,
+        delta -> Nullable<Blob>,
     }
 }
 
@@ -12,6 +15,28 @@ table! {
         id -> Unsigned<Integer>,
         title -> Text,
         markdown -> Nullable<Text>,
+        slug -> Nullable<Varchar>,
+        created_at -> Unsigned<Integer>,
+        updated_at -> Unsigned<Integer>,
+        created_by -> Nullable<Unsigned<Integer>>,
+        updated_by -> Nullable<Unsigned<Integer>>,
+        revision -> Unsigned<Integer>,
+        deleted_at -> Nullable<Unsigned<Integer>>,
+    }
+}
+
+table! {
+    post_metadata (post_id, name, value) {
+        post_id -> Unsigned<Integer>,
+        name -> Varchar,
+        value -> Varchar,
+    }
+}
+
+table! {
+    post_slug_redirects (slug) {
+        slug -> Varchar,
+        post_id -> Unsigned<Integer>,
     }
 }
 
@@ -20,6 +45,23 @@ table! {
         id -> Unsigned<Integer>,
         from_post -> Unsigned<Integer>,
         to_post -> Unsigned<Integer>,
+        relation -> Unsigned<TinyInt>,
+        deleted_at -> Nullable<Unsigned<Integer>>,
+        reattached_from -> Nullable<Unsigned<Integer>>,
+    }
+}
+
+table! {
+    post_tags (post_id, tag_id) {
+        post_id -> Unsigned<Integer>,
+        tag_id -> Unsigned<Integer>,
+    }
+}
+
+table! {
+    tags (id) {
+        id -> Unsigned<Integer>,
+        name -> Varchar,
     }
 }
 
@@ -28,6 +70,7 @@ table! {
         id -> Unsigned<Integer>,
         user_id -> Unsigned<Integer>,
         token -> Text,
+        time -> Unsigned<Integer>,
     }
 }
 
@@ -41,10 +84,16 @@ table! {
     }
 }
 
+joinable!(post_tags -> tags (tag_id));
+
 allow_tables_to_appear_in_same_query!(
     histories,
     posts,
     post_edge,
+    post_metadata,
+    post_slug_redirects,
+    post_tags,
+    tags,
     tokens,
     users,
 );
@@ -0,0 +1,81 @@
+//! 持有数据库连接与配置的服务对象
+use crate::auth::{Auth, AuthUser};
+use crate::config::NotesConfig;
+use crate::post::Post;
+use crate::retention::{PruneReport, RetentionPolicy};
+use crate::user::User;
+use crate::{DbConn, NoteError};
+
+/// 按配置调用各个模块
+pub struct Notes {
+    conn: DbConn,
+    config: NotesConfig,
+}
+
+impl Notes {
+    pub fn new(conn: DbConn, config: NotesConfig) -> Notes {
+        Notes { conn, config }
+    }
+    /// 按配置连接数据库，并检查数据库版本
+    pub fn connect(config: NotesConfig) -> Result<Notes, NoteError> {
+        let conn = crate::connect(&config.database_url)?;
+        Ok(Notes::new(conn, config))
+    }
+
+    pub fn conn(&self) -> &DbConn {
+        &self.conn
+    }
+    pub fn config(&self) -> &NotesConfig {
+        &self.config
+    }
+
+    /// 登陆，Token 按配置的有效期验证
+    pub fn login(&self, auth: Auth) -> Result<AuthUser, NoteError> {
+        if let Auth::Token(_) = auth {
+            if !self.config.features.token_auth {
+                return Err(NoteError::NoPermission(String::from(
+                    "Token auth is disabled",
+                )));
+            }
+        }
+        AuthUser::authenticate(auth, &self.conn, self.config.token_lifetime)
+    }
+    /// 为用户增加一个配置长度的 Token
+    pub fn add_token(&self, user: &AuthUser) -> Result<String, NoteError> {
+        user.add_token_with_len(&self.conn, self.config.token_len)
+    }
+    /// 注册新用户
+    pub fn register(&self, user: &mut User) -> Result<u32, NoteError> {
+        if !self.config.features.registration {
+            return Err(NoteError::NoPermission(String::from(
+                "Registration is disabled",
+            )));
+        }
+        user.insert_with_cost(&self.conn, self.config.hash_cost)
+    }
+    /// 更新用户资料
+    pub fn update_user(&self, user: &User, auth: &AuthUser) -> Result<(), NoteError> {
+        user.update_with_cost(&self.conn, auth, self.config.hash_cost)
+    }
+    /// 插入文章，挂在配置的根文章下
+    pub fn insert_post(&self, post: &Post, user: &AuthUser) -> Result<u32, NoteError> {
+        post.insert_under(&self.conn, user, self.config.root_post_id)
+    }
+    /// 将文章移入回收站，配置的根文章与 Index 文章不能删除，没有上级的下级文章改挂到根文章
+    pub fn delete_post(&self, post: &Post, user: &AuthUser) -> Result<(), NoteError> {
+        post.trash(&self.conn, user, self.config.root_post_id)
+    }
+    /// 彻底删除回收站中超过配置保留天数的文章，需要管理员权限，返回被删除的文章 id
+    pub fn purge_trash(&self, user: &AuthUser) -> Result<Vec<u32>, NoteError> {
+        Post::purge_trash(&self.conn, user, self.config.trash_retention_days)
+    }
+    /// 按配置的保留策略精简历史记录，需要管理员权限，`dry_run` 为真时只生成报告
+    pub fn prune_histories(
+        &self,
+        user: &AuthUser,
+        dry_run: bool,
+    ) -> Result<PruneReport, NoteError> {
+        let policy = RetentionPolicy::from_config(&self.config);
+        crate::retention::prune(&self.conn, user, &policy, dry_run)
+    }
+}
@@ -0,0 +1,431 @@
+//! 生成静态网站
+//!
+//! 只发布从根文章出发沿上下级关系可以到达的文章。根文章生成 `index.html`，其余文章生成 `<slug>.html`，
+//! 页面带有由上下级关系生成的导航、面包屑与反向链接，文中的 wiki 链接指向对应的页面。
+//! `search-index.json` 供页面中的搜索使用，每篇文章的历史记录放在 `history/<文件名>/` 中。
+//! 生成时不会删除输出目录中已有的文件
+use crate::edge::{Edge, Subtree};
+use crate::history::History;
+use crate::link::LinkRenderer;
+use crate::post::Post;
+use crate::render::{escape_html, Renderer};
+use crate::user::User;
+use crate::{DbConn, NoteError};
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+const STYLE: &str = "\
+body { margin: 0; display: flex; font-family: sans-serif; line-height: 1.6; color: #222; }
+nav.sidebar { width: 16rem; flex-shrink: 0; padding: 1rem; border-right: 1px solid #ddd; min-height: 100vh; }
+nav.sidebar ul { list-style: none; padding-left: 1rem; margin: 0; }
+nav.sidebar > ul { padding-left: 0; }
+.site-title { display: block; font-weight: bold; margin-bottom: 0.5rem; }
+#search { width: 100%; box-sizing: border-box; margin-bottom: 0.5rem; }
+#search-results:empty { display: none; }
+main { flex: 1; max-width: 50rem; padding: 1rem 2rem; }
+.breadcrumbs { color: #666; font-size: 0.9rem; }
+.meta { color: #666; font-size: 0.9rem; border-top: 1px solid #ddd; margin-top: 2rem; }
+pre { background: #f6f6f6; padding: 0.5rem; overflow-x: auto; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ddd; padding: 0.25rem 0.5rem; }
+";
+
+const SEARCH_SCRIPT: &str = "\
+(function () {
+  var input = document.getElementById('search');
+  var results = document.getElementById('search-results');
+  var base = document.body.getAttribute('data-base');
+  var index = null;
+
+  function show(query) {
+    results.innerHTML = '';
+    if (!query) return;
+    var matched = index.filter(function (entry) {
+      return entry.title.toLowerCase().indexOf(query) >= 0
+        || entry.text.toLowerCase().indexOf(query) >= 0
+        || entry.tags.some(function (tag) { return tag.toLowerCase() === query; });
+    });
+    matched.slice(0, 20).forEach(function (entry) {
+      var item = document.createElement('li');
+      var link = document.createElement('a');
+      link.href = base + entry.url;
+      link.textContent = entry.title;
+      item.appendChild(link);
+      results.appendChild(item);
+    });
+  }
+
+  input.addEventListener('input', function () {
+    var query = input.value.trim().toLowerCase();
+    if (index) return show(query);
+    fetch(base + 'search-index.json')
+      .then(function (response) { return response.json(); })
+      .then(function (entries) { index = entries; show(input.value.trim().toLowerCase()); });
+  });
+})();
+";
+
+/// 生成选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteOptions {
+    /// 发布的根文章
+    pub root_id: u32,
+    /// 网站标题
+    pub title: String,
+    /// 是否生成历史记录页面
+    pub history: bool,
+}
+
+/// 生成结果
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SiteReport {
+    /// 文章页面数
+    pub pages: usize,
+    /// 历史记录页面数
+    pub history_pages: usize,
+}
+
+/// 搜索索引中的一项
+#[derive(Debug, Serialize, Deserialize)]
+struct SearchEntry {
+    title: String,
+    url: String,
+    tags: Vec<String>,
+    text: String,
+}
+
+/// 将 `options.root_id` 可以到达的文章生成为静态网站，写到目录 `out`
+pub fn build(conn: &DbConn, out: &Path, options: &SiteOptions) -> Result<SiteReport, NoteError> {
+    Post::from_id(conn, options.root_id)?;
+    let subtree = Edge::get_subtree(conn, options.root_id, None)?;
+    let mut posts = vec![];
+    for node in &subtree.nodes {
+        match Post::from_id(conn, node.post_id) {
+            Ok(post) => posts.push(post),
+            Err(NoteError::NotFound { .. }) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    let mut used = HashSet::new();
+    let mut files = HashMap::new();
+    for post in &posts {
+        let mut stem = match post.get_slug() {
+            _ if post.get_id() == options.root_id => String::from("index"),
+            Some(slug) if !slug.is_empty() && slug != "index" => String::from(slug),
+            _ => format!("post-{}", post.get_id()),
+        };
+        if !used.insert(stem.clone()) {
+            stem = format!("{}-{}", stem, post.get_id());
+            used.insert(stem.clone());
+        }
+        files.insert(post.get_id(), stem);
+    }
+
+    let site = Site {
+        conn,
+        out,
+        options,
+        subtree: &subtree,
+        titles: posts
+            .iter()
+            .map(|post| (post.get_id(), String::from(post.get_title())))
+            .collect(),
+        files,
+    };
+    site.write(Path::new("style.css"), STYLE)?;
+    site.write(Path::new("search.js"), SEARCH_SCRIPT)?;
+
+    let mut report = SiteReport::default();
+    let mut search = vec![];
+    let mut users = HashMap::new();
+    for post in &posts {
+        site.write_post(post)?;
+        report.pages += 1;
+        if options.history {
+            report.history_pages += site.write_history(post, &mut users)?;
+        }
+        search.push(SearchEntry {
+            title: String::from(post.get_title()),
+            url: site.url(post.get_id()),
+            tags: post
+                .tags(conn)?
+                .iter()
+                .map(|tag| String::from(tag.get_name()))
+                .collect(),
+            text: crate::render::plain_text(post.get_markdown()),
+        });
+    }
+    let index = serde_json::to_string(&search).map_err(|err| {
+        NoteError::Validation(format!("Failed to serialize search index: {}", err))
+    })?;
+    site.write(Path::new("search-index.json"), &index)?;
+
+    Ok(report)
+}
+
+struct Site<'a> {
+    conn: &'a DbConn,
+    out: &'a Path,
+    options: &'a SiteOptions,
+    subtree: &'a Subtree,
+    /// 发布的文章的标题
+    titles: HashMap<u32, String>,
+    /// 发布的文章的文件名，不含扩展名
+    files: HashMap<u32, String>,
+}
+
+impl Site<'_> {
+    /// 文章页面相对于网站根目录的地址
+    fn url(&self, post_id: u32) -> String {
+        format!("{}.html", crate::slug::encode(&self.files[&post_id]))
+    }
+
+    fn link(&self, base: &str, post_id: u32) -> String {
+        format!(
+            "<a href=\"{}{}\">{}</a>",
+            base,
+            self.url(post_id),
+            escape_html(&self.titles[&post_id])
+        )
+    }
+
+    /// 渲染器，文中的链接相对于 `base`
+    fn renderer(&self, base: &str) -> Renderer {
+        let mut links = LinkRenderer::new();
+        for node in &self.subtree.nodes {
+            if let Some(title) = self.titles.get(&node.post_id) {
+                links.add(
+                    node.post_id,
+                    title,
+                    format!("{}{}", base, self.url(node.post_id)),
+                );
+            }
+        }
+        let mut renderer = Renderer::new();
+        renderer.add_extension(Box::new(links));
+        renderer
+    }
+
+    /// 由生成树得到的导航
+    fn nav(&self, base: &str) -> String {
+        let mut children = HashMap::<u32, Vec<u32>>::new();
+        for node in &self.subtree.nodes {
+            if let Some(parent) = node.parent {
+                if self.titles.contains_key(&node.post_id) {
+                    children.entry(parent).or_default().push(node.post_id);
+                }
+            }
+        }
+
+        let mut html = String::new();
+        if let Some(list) = children.get(&self.options.root_id) {
+            self.nav_list(base, list, &children, &mut html);
+        }
+        html
+    }
+
+    fn nav_list(
+        &self,
+        base: &str,
+        list: &[u32],
+        children: &HashMap<u32, Vec<u32>>,
+        html: &mut String,
+    ) {
+        html.push_str("<ul>");
+        for post_id in list {
+            html.push_str("<li>");
+            html.push_str(&self.link(base, *post_id));
+            if let Some(list) = children.get(post_id) {
+                self.nav_list(base, list, children, html);
+            }
+            html.push_str("</li>");
+        }
+        html.push_str("</ul>");
+    }
+
+    /// 从根文章到 `post_id` 的上级文章
+    fn breadcrumbs(&self, base: &str, post_id: u32) -> String {
+        let parents = self
+            .subtree
+            .nodes
+            .iter()
+            .map(|node| (node.post_id, node.parent))
+            .collect::<HashMap<u32, Option<u32>>>();
+
+        let mut path = vec![];
+        let mut current = parents.get(&post_id).copied().flatten();
+        while let Some(parent) = current {
+            if self.titles.contains_key(&parent) {
+                path.push(self.link(base, parent));
+            }
+            current = parents.get(&parent).copied().flatten();
+        }
+        path.reverse();
+
+        match path.is_empty() {
+            true => String::new(),
+            false => format!("<div class=\"breadcrumbs\">{}</div>", path.join(" / ")),
+        }
+    }
+
+    fn page(&self, base: &str, title: &str, body: &str) -> String {
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+             <title>{title} - {site}</title>\n<link rel=\"stylesheet\" href=\"{base}style.css\">\n\
+             </head>\n<body data-base=\"{base}\">\n<nav class=\"sidebar\">\n\
+             <a class=\"site-title\" href=\"{base}index.html\">{site}</a>\n\
+             <input id=\"search\" type=\"search\" placeholder=\"Search\">\n\
+             <ul id=\"search-results\"></ul>\n{nav}\n</nav>\n<main>\n{body}\n</main>\n\
+             <script src=\"{base}search.js\"></script>\n</body>\n</html>\n",
+            title = escape_html(title),
+            site = escape_html(&self.options.title),
+            base = base,
+            nav = self.nav(base),
+            body = body
+        )
+    }
+
+    fn write_post(&self, post: &Post) -> Result<(), NoteError> {
+        let post_id = post.get_id();
+        let mut body = self.breadcrumbs("", post_id);
+        body.push_str(&format!("<h1>{}</h1>\n", escape_html(post.get_title())));
+        body.push_str(&self.renderer("").render(post.get_markdown()));
+
+        let mut children = vec![];
+        for edge in &self.subtree.edges {
+            if edge.get_from() == post_id
+                && self.titles.contains_key(&edge.get_to())
+                && !children.contains(&edge.get_to())
+            {
+                children.push(edge.get_to());
+            }
+        }
+        if !children.is_empty() {
+            body.push_str("<h2>Children</h2>\n<ul>\n");
+            for child in children {
+                body.push_str(&format!("<li>{}</li>\n", self.link("", child)));
+            }
+            body.push_str("</ul>\n");
+        }
+
+        let backlinks = crate::link::backlinks(self.conn, post)?
+            .into_iter()
+            .filter(|backlink| self.titles.contains_key(&backlink.post_id))
+            .collect::<Vec<_>>();
+        if !backlinks.is_empty() {
+            body.push_str("<h2>Backlinks</h2>\n<ul>\n");
+            for backlink in backlinks {
+                body.push_str(&format!("<li>{}", self.link("", backlink.post_id)));
+                for snippet in &backlink.snippets {
+                    body.push_str(&format!(
+                        "<blockquote>{}</blockquote>",
+                        escape_html(snippet)
+                    ));
+                }
+                body.push_str("</li>\n");
+            }
+            body.push_str("</ul>\n");
+        }
+
+        body.push_str(&format!(
+            "<div class=\"meta\">Updated {}",
+            format_time(post.get_updated_at())
+        ));
+        if self.options.history {
+            body.push_str(&format!(
+                " · <a href=\"history/{}/index.html\">History</a>",
+                crate::slug::encode(&self.files[&post_id])
+            ));
+        }
+        body.push_str("</div>\n");
+
+        let file = format!("{}.html", self.files[&post_id]);
+        self.write(Path::new(&file), &self.page("", post.get_title(), &body))
+    }
+
+    /// 生成文章的历史记录页面，返回页面数
+    fn write_history(
+        &self,
+        post: &Post,
+        users: &mut HashMap<u32, String>,
+    ) -> Result<usize, NoteError> {
+        let base = "../../";
+        let dir = Path::new("history").join(&self.files[&post.get_id()]);
+        let history_list = History::get_history(post.get_id(), self.conn)?;
+        let renderer = self.renderer(base);
+
+        let mut index = format!(
+            "<div class=\"breadcrumbs\">{}</div>\n<h1>History of {}</h1>\n\
+             <table>\n<tr><th>Time</th><th>Author</th></tr>\n",
+            self.link(base, post.get_id()),
+            escape_html(post.get_title())
+        );
+        let mut previous = "";
+        for history in &history_list {
+            let author = match history.get_user_id() {
+                Some(user_id) => match users.get(&user_id) {
+                    Some(nickname) => nickname.clone(),
+                    None => {
+                        let nickname = User::from_user_id(user_id, self.conn)
+                            .map(|user| String::from(user.get_nickname()))
+                            .unwrap_or_else(|_| format!("#{}", user_id));
+                        users.insert(user_id, nickname.clone());
+                        nickname
+                    }
+                },
+                None => String::from("unknown"),
+            };
+            let time = format_time(history.get_time());
+            index.push_str(&format!(
+                "<tr><td><a href=\"{}.html\">{}</a></td><td>{}</td></tr>\n",
+                history.get_id(),
+                time,
+                escape_html(&author)
+            ));
+
+            let diff =
+                crate::diff::unified(previous, history.get_markdown(), "previous", "current");
+            let body = format!(
+                "<div class=\"breadcrumbs\">{} / <a href=\"index.html\">History</a></div>\n\
+                 <h1>{}</h1>\n<p class=\"meta\">{} by {}</p>\n{}\n\
+                 <details><summary>Changes</summary><pre>{}</pre></details>\n",
+                self.link(base, post.get_id()),
+                escape_html(post.get_title()),
+                time,
+                escape_html(&author),
+                renderer.render(history.get_markdown()),
+                escape_html(&diff)
+            );
+            let file = dir.join(format!("{}.html", history.get_id()));
+            self.write(&file, &self.page(base, post.get_title(), &body))?;
+            previous = history.get_markdown();
+        }
+        index.push_str("</table>\n");
+        self.write(
+            &dir.join("index.html"),
+            &self.page(base, post.get_title(), &index),
+        )?;
+
+        Ok(history_list.len() + 1)
+    }
+
+    fn write(&self, file: &Path, content: &str) -> Result<(), NoteError> {
+        let path = self.out.join(file);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| NoteError::from_io(parent, err))?;
+        }
+        std::fs::write(&path, content).map_err(|err| NoteError::from_io(&path, err))
+    }
+}
+
+fn format_time(time: u32) -> String {
+    use chrono::TimeZone;
+
+    match chrono::Utc.timestamp_opt(i64::from(time), 0).single() {
+        Some(time) if time.timestamp() > 0 => time.format("%Y-%m-%d %H:%M UTC").to_string(),
+        _ => String::from("unknown"),
+    }
+}
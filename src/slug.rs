@@ -0,0 +1,160 @@
+//! 文章的 slug，用于稳定的链接
+use crate::raw::RawSlugRedirect;
+use crate::{DbConn, NoteError};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// slug 的最大字符数，需要小于数据库中的长度限制
+const MAX_SLUG_LEN: usize = 96;
+
+/// URL 中需要编码的字符，保留 `-` 与 `_`
+const SLUG_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_');
+
+/// 由标题生成 slug
+///
+/// 英文转为小写，中日韩等文字原样保留，其余字符替换为 `-`
+pub fn slugify(title: &str) -> String {
+    let slug = crate::render::anchor_id(title)
+        .chars()
+        .take(MAX_SLUG_LEN)
+        .collect::<String>();
+    let slug = slug.trim_matches('-');
+    match slug.is_empty() {
+        true => String::from("post"),
+        false => String::from(slug),
+    }
+}
+
+/// 将 slug 编码为 URL 路径的一段
+pub fn encode(slug: &str) -> String {
+    utf8_percent_encode(slug, SLUG_ENCODE_SET).to_string()
+}
+
+/// 解码 URL 路径中的 slug，无效的 UTF-8 会被替换
+pub fn decode(segment: &str) -> String {
+    percent_decode_str(segment).decode_utf8_lossy().into_owned()
+}
+
+/// 由 `base` 生成一个没有被其他文章使用的 slug，冲突时依次加上 `-2`、`-3`……
+pub fn unique_slug(conn: &DbConn, base: &str, post_id: u32) -> Result<String, NoteError> {
+    let mut candidate = String::from(base);
+    let mut count = 1;
+    loop {
+        match owner_of(conn, &candidate)? {
+            Some(owner) if owner != post_id => {
+                count += 1;
+                candidate = format!("{}-{}", base, count);
+            }
+            _ => return Ok(candidate),
+        }
+    }
+}
+
+/// 查找 slug 对应的文章，包括已经改名的旧 slug
+pub fn owner_of(conn: &DbConn, query_slug: &str) -> Result<Option<u32>, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::{post_slug_redirects, posts};
+
+    let current = posts::table
+        .select(posts::id)
+        .filter(posts::slug.eq(query_slug))
+        .first::<u32>(conn)
+        .optional()
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to query slug {}", query_slug), err)
+        })?;
+    if current.is_some() {
+        return Ok(current);
+    }
+
+    post_slug_redirects::table
+        .select(post_slug_redirects::post_id)
+        .filter(post_slug_redirects::slug.eq(query_slug))
+        .first::<u32>(conn)
+        .optional()
+        .map_err(|err| NoteError::from_diesel(format!("Failed to query slug {}", query_slug), err))
+}
+
+/// 将 `post_id` 的 slug 从 `old_slug` 改为 `new_slug`，旧 slug 继续指向这篇文章
+pub fn rename(
+    conn: &DbConn,
+    post_id: u32,
+    old_slug: &str,
+    new_slug: &str,
+) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::{post_slug_redirects, posts};
+
+    diesel::delete(post_slug_redirects::table.filter(post_slug_redirects::slug.eq(new_slug)))
+        .execute(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to delete slug {}", new_slug), err)
+        })?;
+    diesel::update(posts::table.filter(posts::id.eq(post_id)))
+        .set(posts::slug.eq(new_slug))
+        .execute(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to update slug of {}", post_id), err)
+        })?;
+
+    if !old_slug.is_empty() && old_slug != new_slug {
+        diesel::insert_into(post_slug_redirects::table)
+            .values(RawSlugRedirect {
+                slug: String::from(old_slug),
+                post_id,
+            })
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to insert slug {}", old_slug), err)
+            })?;
+    }
+
+    Ok(())
+}
+
+/// 删除指向 `post_id` 的所有旧 slug
+pub fn remove_redirects(conn: &DbConn, post_id: u32) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::post_slug_redirects;
+
+    diesel::delete(post_slug_redirects::table.filter(post_slug_redirects::post_id.eq(post_id)))
+        .execute(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to delete slugs of {}", post_id), err)
+        })?;
+    Ok(())
+}
+
+/// 为还没有 slug 的文章生成 slug
+pub fn backfill(conn: &DbConn) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::posts::dsl::*;
+
+    let missing = posts
+        .select((id, title))
+        .filter(slug.is_null())
+        .order(id.asc())
+        .load::<(u32, String)>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query posts"), err))?;
+
+    for (post_id, post_title) in missing {
+        let new_slug = unique_slug(conn, &slugify(&post_title), post_id)?;
+        rename(conn, post_id, "", &new_slug)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, slugify};
+
+    #[test]
+    fn slug_keeps_cjk_and_encodes_for_url() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("笔记 索引"), "笔记-索引");
+        assert_eq!(slugify("?!"), "post");
+        assert_eq!(encode("笔记-a_b"), "%E7%AC%94%E8%AE%B0-a_b");
+        assert_eq!(decode(&encode("笔记-a_b")), "笔记-a_b");
+    }
+}
@@ -0,0 +1,563 @@
+//! 文章的标签，来自文中的 `#标签` 与 front matter 中的 `tags`
+use crate::auth::{AuthUpdate, AuthUser};
+use crate::front_matter::Format;
+use crate::insert::InsertTag;
+use crate::post::Post;
+use crate::raw::{RawPostTag, RawTag};
+use crate::{DbConn, NoteError};
+
+use std::ops::Range;
+
+/// 标签名的最大字节数，与数据库中的长度限制一致
+const MAX_TAG_LEN: usize = 191;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tag {
+    id: u32,
+    name: String,
+}
+
+impl Tag {
+    pub fn get_id(&self) -> u32 {
+        self.id
+    }
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn from_id(conn: &DbConn, tag_id: u32) -> Result<Tag, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::tags::dsl::*;
+
+        Ok(Tag::from(
+            &tags
+                .filter(id.eq(tag_id))
+                .first::<RawTag>(conn)
+                .map_err(|err| NoteError::from_query("tag", tag_id, err))?,
+        ))
+    }
+    /// 通过名字获取标签，不区分大小写
+    pub fn from_name(conn: &DbConn, tag_name: &str) -> Result<Tag, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::tags::dsl::*;
+
+        let tag_name = normalize(tag_name);
+        Ok(Tag::from(
+            &tags
+                .filter(name.eq(&tag_name))
+                .first::<RawTag>(conn)
+                .map_err(|err| NoteError::from_query("tag", &tag_name, err))?,
+        ))
+    }
+
+    /// 获取所有标签，按名字排序
+    pub fn list(conn: &DbConn) -> Result<Vec<Tag>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::tags::dsl::*;
+
+        Ok(tags
+            .order(name.asc())
+            .load::<RawTag>(conn)
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to query tags"), err))?
+            .iter()
+            .map(Tag::from)
+            .collect::<Vec<Tag>>())
+    }
+    /// 获取文章的所有标签，按名字排序
+    pub fn get_post_tags(conn: &DbConn, query_id: u32) -> Result<Vec<Tag>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::{post_tags, tags};
+
+        Ok(tags::table
+            .inner_join(post_tags::table)
+            .filter(post_tags::post_id.eq(query_id))
+            .select((tags::id, tags::name))
+            .order(tags::name.asc())
+            .load::<RawTag>(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to query tags of {}", query_id), err)
+            })?
+            .iter()
+            .map(Tag::from)
+            .collect::<Vec<Tag>>())
+    }
+    /// 获取带有当前标签的所有文章的 id，不包括回收站中的文章
+    pub fn get_post_ids(&self, conn: &DbConn) -> Result<Vec<u32>, NoteError> {
+        use crate::diesel::*;
+        use crate::schema::post_tags::dsl::*;
+        use crate::schema::posts;
+
+        let active = posts::table
+            .select(posts::id)
+            .filter(posts::deleted_at.is_null());
+        post_tags
+            .select(post_id)
+            .filter(tag_id.eq(self.id))
+            .filter(post_id.eq_any(active))
+            .order(post_id.asc())
+            .load::<u32>(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to query posts of tag {}", self.id), err)
+            })
+    }
+
+    /// 重命名标签，同时改写所有带有该标签的文章，全部在一个事务中完成
+    ///
+    /// 新名字已被其他标签使用时返回 `NoteError::Conflict`，此时应使用 `merge`
+    pub fn rename(&self, conn: &DbConn, user: &AuthUser, new_name: &str) -> Result<(), NoteError> {
+        use crate::diesel::*;
+        use crate::schema::tags::dsl::*;
+
+        user.auth()?;
+
+        let new_name = validate(new_name)?;
+        match Tag::from_name(conn, &new_name) {
+            Ok(tag) if tag.id != self.id => {
+                return Err(NoteError::conflict(format!(
+                    "Tag {} already exists",
+                    new_name
+                )))
+            }
+            Ok(_) | Err(NoteError::NotFound { .. }) => (),
+            Err(err) => return Err(err),
+        }
+
+        conn.transaction::<_, NoteError, _>(|| {
+            diesel::update(tags.filter(id.eq(self.id)))
+                .set(InsertTag {
+                    name: new_name.clone(),
+                })
+                .execute(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(format!("Failed to rename tag {}", self.id), err)
+                })?;
+            self.retag(conn, user, &new_name)
+        })
+    }
+    /// 将当前标签合并到 `into`，同时改写所有带有该标签的文章，全部在一个事务中完成
+    pub fn merge(&self, conn: &DbConn, user: &AuthUser, into: &Tag) -> Result<(), NoteError> {
+        use crate::diesel::*;
+
+        user.auth()?;
+
+        if into.id == self.id {
+            return Ok(());
+        }
+        conn.transaction::<_, NoteError, _>(|| {
+            self.retag(conn, user, into.get_name())?;
+            remove_unused(conn, &[self.id])
+        })
+    }
+
+    /// 将带有当前标签的文章中的标签改为 `new_name`，失败时错误信息中带有文章 id
+    fn retag(&self, conn: &DbConn, user: &AuthUser, new_name: &str) -> Result<(), NoteError> {
+        for post_id in self.get_post_ids(conn)? {
+            let retag_post = || {
+                let post = Post::from_id(conn, post_id)?;
+                let markdown = replace_tag(post.get_markdown(), &self.name, new_name);
+                let mut updated = Post::new(
+                    Some(post_id),
+                    String::from(post.get_title()),
+                    Some(markdown),
+                );
+                updated.set_revision(post.get_revision());
+                updated.update(conn, user)
+            };
+            retag_post()
+                .map_err(|err| err.with_context(&format!("Failed to retag post {}", post_id)))?;
+        }
+        Ok(())
+    }
+}
+
+/// 标签名不区分大小写，统一存为小写
+pub fn normalize(name: &str) -> String {
+    name.trim().trim_start_matches('#').to_lowercase()
+}
+
+/// 检查并规范化标签名
+pub fn validate(name: &str) -> Result<String, NoteError> {
+    let name = normalize(name);
+    if name.is_empty()
+        || name.len() > MAX_TAG_LEN
+        || !name.chars().all(is_tag_char)
+        || name.chars().all(|c| c.is_ascii_digit())
+    {
+        return Err(NoteError::Validation(format!("Invalid tag name: {}", name)));
+    }
+    Ok(name)
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '/'
+}
+
+/// 找出文中所有标签及标签名（不含 `#`）的位置
+///
+/// 支持 front matter 中的 `tags: [a, b]`、`tags: a, b`、列表写法以及 TOML 的 `tags = [...]`，
+/// 正文中的 `#标签` 需位于行首或空白之后，跳过代码块与行内代码，纯数字不视为标签
+// `Option::is_none_or` 需要 Rust 1.82
+#[allow(clippy::unnecessary_map_or)]
+pub fn find_tags(markdown: &str) -> Vec<(String, Range<usize>)> {
+    let mut tags = vec![];
+    let body_start = front_matter_tags(markdown, &mut tags);
+
+    let mut in_fence = false;
+    let mut offset = body_start;
+    for line in markdown[body_start..].split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        let mut in_code = false;
+        let mut prev = None;
+        for (pos, c) in line.char_indices() {
+            if c == '`' {
+                in_code = !in_code;
+            } else if c == '#' && !in_code && prev.map_or(true, char::is_whitespace) {
+                let rest = &line[pos + 1..];
+                let len = rest
+                    .char_indices()
+                    .find(|(_, c)| !is_tag_char(*c))
+                    .map(|(index, _)| index)
+                    .unwrap_or_else(|| rest.len());
+                let name = rest[..len].trim_end_matches(&['-', '/'][..]);
+                if !name.is_empty() && !name.chars().all(|c| c.is_ascii_digit()) {
+                    let start = line_start + pos + 1;
+                    tags.push((normalize(name), start..start + name.len()));
+                }
+            }
+            prev = Some(c);
+        }
+    }
+
+    tags
+}
+
+/// 解析 front matter 中的标签，返回正文的起始位置
+///
+/// 为了能原地改写标签，这里按行查找 `tags` 字段，而不是使用解析后的元数据
+fn front_matter_tags(markdown: &str, tags: &mut Vec<(String, Range<usize>)>) -> usize {
+    let front_matter = match crate::front_matter::split(markdown) {
+        Some(front_matter) => front_matter,
+        None => return 0,
+    };
+    let separator = match front_matter.format {
+        Format::Yaml => ':',
+        Format::Toml => '=',
+    };
+
+    let mut offset = front_matter.content_start;
+    let mut in_tags = false;
+    for line in front_matter.content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+
+        let content = line.trim_end();
+        let value = ["tags", "tag"].iter().find_map(|key| {
+            content
+                .strip_prefix(key)
+                .and_then(|rest| rest.trim_start().strip_prefix(separator))
+        });
+        if let Some(value) = value {
+            let value_start = line_start + content.len() - value.len();
+            let value_trimmed = value.trim();
+            in_tags = value_trimmed.is_empty();
+            if let Some(inner) = value_trimmed
+                .strip_prefix('[')
+                .and_then(|inner| inner.strip_suffix(']'))
+            {
+                let inner_start = value_start + value.find('[').unwrap_or(0) + 1;
+                push_items(inner, inner_start, &[','], tags);
+            } else {
+                push_items(value, value_start, &[',', ' '], tags);
+            }
+        } else if in_tags {
+            match content.trim_start().strip_prefix("- ") {
+                Some(item) => push_items(item, line_start + content.len() - item.len(), &[], tags),
+                None => in_tags = content.starts_with(char::is_whitespace),
+            }
+        }
+    }
+
+    front_matter.body_start
+}
+
+/// 按 `separators` 切分 `value`，去掉引号与 `#` 后作为标签
+fn push_items(
+    value: &str,
+    value_start: usize,
+    separators: &[char],
+    tags: &mut Vec<(String, Range<usize>)>,
+) {
+    let mut start = 0;
+    for item in value.split(separators) {
+        let item_start = start;
+        start += item.len() + 1;
+
+        let trimmed = item.trim().trim_matches(&['"', '\''][..]);
+        let trimmed = trimmed.strip_prefix('#').unwrap_or(trimmed);
+        if trimmed.is_empty() || !trimmed.chars().all(is_tag_char) {
+            continue;
+        }
+        let offset = value_start + item_start + item.find(trimmed).unwrap_or(0);
+        tags.push((normalize(trimmed), offset..offset + trimmed.len()));
+    }
+}
+
+/// 找出文中所有不重复的标签
+pub fn parse_tags(markdown: &str) -> Vec<String> {
+    let mut tags = vec![];
+    for (tag, _) in find_tags(markdown) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// 将文中的标签 `old_name` 替换为 `new_name`
+pub fn replace_tag(markdown: &str, old_name: &str, new_name: &str) -> String {
+    let old_name = normalize(old_name);
+    let new_name = normalize(new_name);
+
+    let mut result = String::from(markdown);
+    for (_, range) in find_tags(markdown)
+        .into_iter()
+        .rev()
+        .filter(|(tag, _)| *tag == old_name)
+    {
+        result.replace_range(range, &new_name);
+    }
+    result
+}
+
+/// 获取名为 `tag_name` 的标签的 id，不存在时新建
+fn get_or_insert(conn: &DbConn, tag_name: &str) -> Result<u32, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::tags;
+
+    match Tag::from_name(conn, tag_name) {
+        Ok(tag) => return Ok(tag.id),
+        Err(NoteError::NotFound { .. }) => (),
+        Err(err) => return Err(err),
+    }
+
+    diesel::insert_into(tags::table)
+        .values(InsertTag {
+            name: String::from(tag_name),
+        })
+        .execute(conn)
+        .map_err(|err| NoteError::from_diesel(format!("Failed to insert tag {}", tag_name), err))?;
+    crate::get_last_insert_rowid(conn)
+}
+
+/// 删除 `tag_ids` 中不再被任何文章使用的标签
+fn remove_unused(conn: &DbConn, tag_ids: &[u32]) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::{post_tags, tags};
+
+    let used = post_tags::table
+        .select(post_tags::tag_id)
+        .filter(post_tags::tag_id.eq_any(tag_ids))
+        .load::<u32>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query tags"), err))?;
+    let unused = tag_ids
+        .iter()
+        .filter(|tag_id| !used.contains(tag_id))
+        .collect::<Vec<&u32>>();
+    if unused.is_empty() {
+        return Ok(());
+    }
+
+    diesel::delete(tags::table.filter(tags::id.eq_any(unused)))
+        .execute(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to delete tags"), err))?;
+    Ok(())
+}
+
+/// 按 `markdown` 中的标签同步 `post_id` 的标签，返回当前的标签名
+pub fn sync(
+    conn: &DbConn,
+    user: &AuthUser,
+    post_id: u32,
+    markdown: &str,
+) -> Result<Vec<String>, NoteError> {
+    use crate::diesel::*;
+    use crate::schema::post_tags;
+
+    user.auth()?;
+
+    let names = parse_tags(markdown)
+        .into_iter()
+        .filter(|name| validate(name).is_ok())
+        .collect::<Vec<String>>();
+    let mut tag_ids = vec![];
+    for name in &names {
+        tag_ids.push(get_or_insert(conn, name)?);
+    }
+    // 数据库按排序规则比较标签名，不同的标签名可能对应同一个标签
+    tag_ids.sort_unstable();
+    tag_ids.dedup();
+
+    let origin_ids = post_tags::table
+        .select(post_tags::tag_id)
+        .filter(post_tags::post_id.eq(post_id))
+        .load::<u32>(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to query tags of {}", post_id), err)
+        })?;
+
+    let removed = origin_ids
+        .iter()
+        .filter(|origin_id| !tag_ids.contains(origin_id))
+        .copied()
+        .collect::<Vec<u32>>();
+    if !removed.is_empty() {
+        diesel::delete(
+            post_tags::table
+                .filter(post_tags::post_id.eq(post_id))
+                .filter(post_tags::tag_id.eq_any(&removed)),
+        )
+        .execute(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to delete tags of {}", post_id), err)
+        })?;
+        remove_unused(conn, &removed)?;
+    }
+
+    let added = tag_ids
+        .iter()
+        .filter(|tag_id| !origin_ids.contains(tag_id))
+        .map(|tag_id| RawPostTag {
+            post_id,
+            tag_id: *tag_id,
+        })
+        .collect::<Vec<RawPostTag>>();
+    if !added.is_empty() {
+        diesel::insert_into(post_tags::table)
+            .values(&added)
+            .execute(conn)
+            .map_err(|err| {
+                NoteError::from_diesel(format!("Failed to insert tags of {}", post_id), err)
+            })?;
+    }
+
+    Ok(names)
+}
+
+/// 删除 `post_id` 的所有标签
+pub fn remove_post(conn: &DbConn, post_id: u32) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::post_tags;
+
+    let origin_ids = post_tags::table
+        .select(post_tags::tag_id)
+        .filter(post_tags::post_id.eq(post_id))
+        .load::<u32>(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to query tags of {}", post_id), err)
+        })?;
+    diesel::delete(post_tags::table.filter(post_tags::post_id.eq(post_id)))
+        .execute(conn)
+        .map_err(|err| {
+            NoteError::from_diesel(format!("Failed to delete tags of {}", post_id), err)
+        })?;
+    remove_unused(conn, &origin_ids)
+}
+
+/// 没有任何标签记录时，从已有文章中解析标签
+pub fn backfill(conn: &DbConn) -> Result<(), NoteError> {
+    use crate::diesel::*;
+    use crate::schema::{post_tags, posts, tags};
+
+    let count = post_tags::table
+        .count()
+        .get_result::<i64>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query tags"), err))?;
+    if count > 0 {
+        return Ok(());
+    }
+
+    let contents = posts::table
+        .select((posts::id, posts::markdown))
+        .load::<(u32, Option<String>)>(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to query posts"), err))?;
+    for (post_id, markdown) in contents {
+        let mut tag_ids = vec![];
+        for name in parse_tags(markdown.as_deref().unwrap_or("")) {
+            if validate(&name).is_ok() {
+                tag_ids.push(get_or_insert(conn, &name)?);
+            }
+        }
+        let rows = tag_ids
+            .into_iter()
+            .map(|tag_id| RawPostTag { post_id, tag_id })
+            .collect::<Vec<RawPostTag>>();
+        if !rows.is_empty() {
+            diesel::insert_into(post_tags::table)
+                .values(&rows)
+                .execute(conn)
+                .map_err(|err| {
+                    NoteError::from_diesel(format!("Failed to insert tags of {}", post_id), err)
+                })?;
+        }
+    }
+
+    // 清理没有文章的标签
+    let used = post_tags::table.select(post_tags::tag_id);
+    diesel::delete(tags::table.filter(tags::id.ne_all(used)))
+        .execute(conn)
+        .map_err(|err| NoteError::from_diesel(String::from("Failed to delete tags"), err))?;
+
+    Ok(())
+}
+
+impl From<&RawTag> for Tag {
+    fn from(tag: &RawTag) -> Tag {
+        Tag {
+            id: tag.id,
+            name: tag.name.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_tags, replace_tag};
+
+    #[test]
+    fn tags_are_parsed_from_front_matter_and_body() {
+        let markdown = "---\ntitle: Note\ntags: [Rust, \"db/mysql\"]\naliases:\n  - x\n---\n\
+                        # Heading #inline\n#Rust is #fun, see [[#12]] or a#b #123\n\
+                        `#code`\n```\n#fenced\n```\n#中文 #todo-";
+
+        assert_eq!(
+            parse_tags(markdown),
+            vec!["rust", "db/mysql", "inline", "fun", "中文", "todo"]
+        );
+        assert_eq!(
+            parse_tags("---\ntags:\n  - a\n  - '#b'\nother: 1\n---\n"),
+            vec!["a", "b"]
+        );
+        assert_eq!(parse_tags("---\ntags: a, b\n"), Vec::<String>::new());
+        assert_eq!(parse_tags("+++\ntags = [\"c\"]\n+++\n#d"), vec!["c", "d"]);
+    }
+
+    #[test]
+    fn tags_are_replaced_in_place() {
+        let markdown = "---\ntags: [rust, db]\n---\nAbout #Rust and #rustacean.";
+        assert_eq!(
+            replace_tag(markdown, "rust", "lang/rust"),
+            "---\ntags: [lang/rust, db]\n---\nAbout #lang/rust and #rustacean."
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! 测试用的数据库
+//!
+//! 需要数据库的测试标记为 `#[ignore]`，在 `NOTES_TEST_DATABASE_URL` 指向一个专用于测试的 MySQL 数据库时运行：
+//! `cargo test -- --ignored --test-threads=1`。每个测试的修改都在测试结束时回滚
+use crate::auth::{AuthLevel, AuthUser};
+use crate::user::User;
+use crate::DbConn;
+
+/// 测试数据库地址所在的环境变量
+pub const DATABASE_URL_ENV: &str = "NOTES_TEST_DATABASE_URL";
+
+/// 连接并迁移测试数据库，之后的修改都在一个不会提交的事务中进行
+pub fn connect() -> DbConn {
+    use diesel::Connection;
+
+    let database_url = std::env::var(DATABASE_URL_ENV)
+        .unwrap_or_else(|_| panic!("{} must point to a test database", DATABASE_URL_ENV));
+    let conn = DbConn::establish(&database_url).expect("Failed to connect to test database");
+    crate::migrate(&conn).expect("Failed to migrate test database");
+    conn.begin_test_transaction()
+        .expect("Failed to begin test transaction");
+    conn
+}
+
+/// 插入一个管理员并以密码登陆
+pub fn admin(conn: &DbConn) -> AuthUser {
+    use crate::diesel::*;
+    use crate::schema::users;
+
+    let mut user = User::new(
+        None,
+        String::from("test-admin"),
+        String::from("password"),
+        String::from("admin@notes.example"),
+    );
+    let user_id = user
+        .insert_with_cost(conn, 4)
+        .expect("Failed to insert test user");
+    diesel::update(users::table.filter(users::id.eq(user_id)))
+        .set(users::admin.eq(true))
+        .execute(conn)
+        .expect("Failed to promote test user");
+    let user = User::from_user_id(user_id, conn).expect("Failed to load test user");
+    AuthUser::from((&user, AuthLevel::Password))
+}
@@ -1,5 +1,6 @@
 //! 通过 Token 登陆
 use crate::auth::{AuthInsert, AuthUser};
+use crate::config::DEFAULT_TOKEN_LEN;
 use crate::insert::InsertToken;
 use crate::raw::RawToken;
 use crate::{gen_token, DbConn, NoteError};
@@ -7,13 +8,20 @@ use crate::{gen_token, DbConn, NoteError};
 pub struct Token {
     user_id: u32,
     token: String,
+    /// 创建时间
+    time: u32,
 }
 
 impl Token {
     pub fn new(user_id: u32) -> Token {
+        Token::with_len(user_id, DEFAULT_TOKEN_LEN)
+    }
+    /// 生成长度为 `token_len` 的 Token
+    pub fn with_len(user_id: u32, token_len: u32) -> Token {
         Token {
             user_id,
-            token: gen_token(),
+            token: gen_token(token_len),
+            time: chrono::Utc::now().timestamp() as u32,
         }
     }
 
@@ -23,6 +31,14 @@ impl Token {
     pub fn get_token(&self) -> &str {
         &self.token
     }
+    pub fn get_time(&self) -> u32 {
+        self.time
+    }
+    /// 在有效期为 `lifetime` 秒时是否已过期，`lifetime` 为 0 表示永不过期
+    pub fn is_expired(&self, lifetime: u64) -> bool {
+        let now = chrono::Utc::now().timestamp() as u64;
+        lifetime != 0 && u64::from(self.time) + lifetime < now
+    }
 
     /// 获取 Token 等于当前值的列表
     pub fn from_token(current_token: &str, conn: &DbConn) -> Result<Vec<Token>, NoteError> {
@@ -32,7 +48,7 @@ impl Token {
         let raw_list = tokens
             .filter(token.eq(current_token))
             .load::<RawToken>(conn)
-            .map_err(|err| NoteError::SQLError(format!("Failed to query token: {}", err)))?;
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to query token"), err))?;
 
         let mut token_list = vec![];
         for raw_token in raw_list.iter() {
@@ -43,10 +59,19 @@ impl Token {
     }
     /// 验证对应 Token 是否合法
     pub fn verify(id: &u32, token: &str, conn: &DbConn) -> Result<bool, NoteError> {
+        Token::verify_within(id, token, 0, conn)
+    }
+    /// 验证对应 Token 是否合法且未超过 `lifetime` 秒的有效期
+    pub fn verify_within(
+        id: &u32,
+        token: &str,
+        lifetime: u64,
+        conn: &DbConn,
+    ) -> Result<bool, NoteError> {
         let token_list = Token::from_token(token, &*conn)?;
 
         for token in token_list.iter() {
-            if token.get_user_id() == *id {
+            if token.get_user_id() == *id && !token.is_expired(lifetime) {
                 return Ok(true);
             }
         }
@@ -69,7 +94,7 @@ impl AuthInsert for Token {
         diesel::insert_into(tokens::table)
             .values(InsertToken::from(&*self))
             .execute(conn)
-            .map_err(|err| NoteError::SQLError(format!("Failed to insert token: {}", err)))?;
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to insert token"), err))?;
 
         crate::get_last_insert_rowid(conn)
     }
@@ -80,6 +105,7 @@ impl From<&Token> for InsertToken {
         InsertToken {
             user_id: token.get_user_id(),
             token: String::from(token.get_token()),
+            time: token.get_time(),
         }
     }
 }
@@ -89,6 +115,7 @@ impl From<&RawToken> for Token {
         Token {
             user_id: raw.user_id,
             token: String::from(&raw.token),
+            time: raw.time,
         }
     }
 }
@@ -49,15 +49,19 @@ impl User {
 
     /// 插入当前用户（很明显，插入用户不需要验证）
     pub fn insert(&mut self, conn: &DbConn) -> Result<u32, NoteError> {
+        self.insert_with_cost(conn, bcrypt::DEFAULT_COST)
+    }
+    /// 以 `hash_cost` 哈希密码并插入当前用户
+    pub fn insert_with_cost(&mut self, conn: &DbConn, hash_cost: u32) -> Result<u32, NoteError> {
         use crate::diesel::*;
         use crate::schema::users;
 
         self.admin = false;
-        self.password = bcrypt::hash(&self.password, bcrypt::DEFAULT_COST).unwrap();
+        self.password = bcrypt::hash(&self.password, hash_cost).unwrap();
         diesel::insert_into(users::table)
             .values(InsertUser::from((&*self, self.password.as_str())))
             .execute(conn)
-            .map_err(|err| NoteError::SQLError(format!("Failed to insert user: {}", err)))?;
+            .map_err(|err| NoteError::from_diesel(String::from("Failed to insert user"), err))?;
 
         crate::get_last_insert_rowid(conn)
     }
@@ -70,12 +74,7 @@ impl User {
             users
                 .filter(nickname.eq(name))
                 .first::<RawUser>(conn)
-                .map_err(|err| {
-                    NoteError::SQLError(format!(
-                        "Failed to query user from nickname{}: {}",
-                        name, err
-                    ))
-                })?,
+                .map_err(|err| NoteError::from_query("user", name, err))?,
         ))
     }
     /// 通过用户 ID 获取用户
@@ -87,15 +86,17 @@ impl User {
             users
                 .filter(id.eq(user_id))
                 .first::<RawUser>(conn)
-                .map_err(|err| {
-                    NoteError::SQLError(format!("Failed to query user from id{}:{}", user_id, err))
-                })?,
+                .map_err(|err| NoteError::from_query("user", user_id, err))?,
         ))
     }
-}
 
-impl AuthUpdate for User {
-    fn update(&self, conn: &DbConn, user: &AuthUser) -> Result<(), NoteError> {
+    /// 以 `hash_cost` 哈希密码并同步进数据库
+    pub fn update_with_cost(
+        &self,
+        conn: &DbConn,
+        user: &AuthUser,
+        hash_cost: u32,
+    ) -> Result<(), NoteError> {
         match user.get_level() {
             AuthLevel::Password => match user.get_id() == self.id {
                 true => {
@@ -104,13 +105,13 @@ impl AuthUpdate for User {
                     diesel::update(users.filter(id.eq(self.id)))
                         .set(InsertUser::from((
                             &*self,
-                            bcrypt::hash(self.password.as_str(), bcrypt::DEFAULT_COST)
+                            bcrypt::hash(self.password.as_str(), hash_cost)
                                 .unwrap()
                                 .as_str(),
                         )))
                         .execute(conn)
                         .map_err(|err| {
-                            NoteError::SQLError(format!("Failed update user {}: {}", self.id, err))
+                            NoteError::from_diesel(format!("Failed update user {}", self.id), err)
                         })?;
                     Ok(())
                 }
@@ -125,6 +126,12 @@ impl AuthUpdate for User {
     }
 }
 
+impl AuthUpdate for User {
+    fn update(&self, conn: &DbConn, user: &AuthUser) -> Result<(), NoteError> {
+        self.update_with_cost(conn, user, bcrypt::DEFAULT_COST)
+    }
+}
+
 impl From<RawUser> for User {
     fn from(raw: RawUser) -> User {
         User {